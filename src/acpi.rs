@@ -0,0 +1,197 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{mem, ptr, slice};
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+// ACPI tables are read through the physical memory mapping, bootloader maps everything up to the highest
+// address in the memory map and firmware tables are part of it (as reserved/ACPI regions)
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const EBDA_POINTER: u64 = 0x40e; // BIOS data area holds real mode segment of extended BIOS data area
+const BIOS_AREA: (u64, u64) = (0xe0000, 0x100000);
+
+static ACPI_INFO: OnceCell<Option<AcpiInfo>> = OnceCell::uninit();
+
+// what the kernel needs from the MADT (multiple APIC description table)
+#[derive(Debug)]
+pub struct AcpiInfo {
+    pub local_apic_address: PhysAddr,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysAddr,
+    pub gsi_base: u32, // first global system interrupt handled by this I/O APIC
+}
+
+// legacy ISA IRQ wired to a different global system interrupt (QEMU routes IRQ0 to GSI 2)
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub source_irq: u8,
+    pub gsi: u32,
+    pub flags: u16, // polarity (bits 0-1) and trigger mode (bits 2-3)
+}
+
+impl AcpiInfo {
+    // global system interrupt and MPS INTI flags for a legacy ISA IRQ
+    pub fn irq_to_gsi(&self, irq: u8) -> (u32, u16) {
+        self.overrides
+            .iter()
+            .find(|o| o.source_irq == irq)
+            .map(|o| (o.gsi, o.flags))
+            .unwrap_or((irq as u32, 0)) // identity mapped, ISA defaults (edge triggered, active high)
+    }
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0+
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+// parses firmware tables once, has to be called after memory::init and allocator::init
+pub fn init() {
+    ACPI_INFO.init_once(|| unsafe { find_rsdp().and_then(|rsdp| parse_madt(find_madt(rsdp)?)) });
+}
+
+// None if ACPI tables (or MADT) weren't found
+pub fn info() -> Option<&'static AcpiInfo> {
+    ACPI_INFO.try_get().ok()?.as_ref()
+}
+
+fn phys_ptr<T>(phys: u64) -> *const T {
+    memory::with_vmm(|vmm| vmm.phys_to_virt(PhysAddr::new(phys))).as_ptr()
+}
+
+// all bytes of a valid table sum up to 0
+unsafe fn checksum_ok(phys: u64, len: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(phys_ptr::<u8>(phys), len) };
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+// RSDP lies on a 16 byte boundary in the first KiB of EBDA or in the BIOS read-only area
+unsafe fn find_rsdp() -> Option<u64> {
+    let ebda = unsafe { ptr::read_unaligned(phys_ptr::<u16>(EBDA_POINTER)) } as u64 * 16;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+
+    areas
+        .into_iter()
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .find(|&addr| unsafe {
+            let signature = ptr::read_unaligned(phys_ptr::<[u8; 8]>(addr));
+            &signature == RSDP_SIGNATURE && checksum_ok(addr, 20) // checksum of ACPI 1.0 part
+        })
+}
+
+// walks XSDT (or RSDT on ACPI 1.0) looking for MADT
+unsafe fn find_madt(rsdp_addr: u64) -> Option<u64> {
+    let rsdp = unsafe { ptr::read_unaligned(phys_ptr::<Rsdp>(rsdp_addr)) };
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (rsdp.rsdt_address as u64, 4)
+    };
+
+    let header = unsafe { ptr::read_unaligned(phys_ptr::<SdtHeader>(root)) };
+    let entries = (header.length as usize - mem::size_of::<SdtHeader>()) / entry_size;
+    let first_entry = root + mem::size_of::<SdtHeader>() as u64;
+
+    (0..entries)
+        .map(|i| unsafe {
+            let entry = first_entry + (i * entry_size) as u64;
+            match entry_size {
+                8 => ptr::read_unaligned(phys_ptr::<u64>(entry)),
+                _ => ptr::read_unaligned(phys_ptr::<u32>(entry)) as u64,
+            }
+        })
+        .find(|&table| unsafe {
+            let header = ptr::read_unaligned(phys_ptr::<SdtHeader>(table));
+            &header.signature == MADT_SIGNATURE && checksum_ok(table, header.length as usize)
+        })
+}
+
+unsafe fn parse_madt(madt: u64) -> Option<AcpiInfo> {
+    let header = unsafe { ptr::read_unaligned(phys_ptr::<SdtHeader>(madt)) };
+    let body = madt + mem::size_of::<SdtHeader>() as u64;
+    let local_apic_address = unsafe { ptr::read_unaligned(phys_ptr::<u32>(body)) } as u64;
+
+    let mut info = AcpiInfo {
+        local_apic_address: PhysAddr::new(local_apic_address),
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    // variable length entries follow local APIC address and flags: type (u8), length (u8), data
+    let bytes = unsafe { slice::from_raw_parts(phys_ptr::<u8>(body + 8), header.length as usize - 44) };
+    let mut offset = 0;
+    while offset + 2 <= bytes.len() {
+        let (entry_type, len) = (bytes[offset], bytes[offset + 1] as usize);
+        if len < 2 || offset + len > bytes.len() {
+            break; // malformed table, keep what was parsed so far
+        }
+        let entry = &bytes[offset..offset + len];
+        let u32_at = |i: usize| u32::from_le_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]]);
+
+        match entry_type {
+            0 => info.processors.push(Processor {
+                acpi_id: entry[2],
+                apic_id: entry[3],
+                enabled: u32_at(4) & 0b11 != 0, // enabled or online capable
+            }),
+            1 => info.io_apics.push(IoApicEntry {
+                id: entry[2],
+                address: PhysAddr::new(u32_at(4) as u64),
+                gsi_base: u32_at(8),
+            }),
+            2 => info.overrides.push(InterruptOverride {
+                source_irq: entry[3],
+                gsi: u32_at(4),
+                flags: u16::from_le_bytes([entry[8], entry[9]]),
+            }),
+            5 => { // 64-bit local APIC address override
+                let addr = u64::from_le_bytes(entry[4..12].try_into().unwrap());
+                info.local_apic_address = PhysAddr::new(addr);
+            }
+            _ => {} // NMI sources, x2APIC entries etc. aren't used
+        }
+        offset += len;
+    }
+
+    Some(info)
+}
@@ -2,38 +2,24 @@ pub mod bump;
 pub mod linked_list;
 pub mod fixed_size_block;
 
-use bootloader::BootInfo;
-use x86_64::{structures::paging::{mapper::{MapToError, Mapper}, FrameAllocator, Page, PageTableFlags, Size4KiB}, VirtAddr};
+use x86_64::{structures::paging::{mapper::MapToError, PageTableFlags, Size4KiB}, VirtAddr};
 
+use crate::memory;
 use fixed_size_block::FixedSizeBlockAllocator;
 
 #[global_allocator]
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
-
-fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = { // create heap's page range
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush()
-        };
-    }
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB, initial size, heap grows on demand after that
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB, heap never grows past this ceiling
+
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    memory::with_vmm(|vmm| {
+        vmm.map_region(VirtAddr::new(HEAP_START as u64), HEAP_SIZE, HEAP_FLAGS)
+    })?;
 
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
@@ -42,15 +28,39 @@ fn init_heap(
     Ok(())
 }
 
-pub fn init(boot_info: &'static BootInfo) {
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { super::memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        super::memory::BootInfoFrameAllocator::init(&boot_info.memory_map)
-    };
+// called by the heap allocator (with its lock held) when it can't satisfy an allocation
+// maps up to 'min_size' bytes (at least) right after 'heap_top' and returns how many bytes were actually added
+// heap grows at least by its current size, so number of grow calls stays logarithmic
+fn grow_heap(heap_top: usize, min_size: usize) -> usize {
+    let current_size = heap_top - HEAP_START;
+    let available = HEAP_MAX_SIZE - current_size;
+    if min_size > available {
+        return 0; // would never fit under the ceiling, so dont waste frames on it
+    }
+    let size = align_up(min_size.max(current_size), memory::PAGE_SIZE).min(available);
+
+    // map page by page, so running out of frames halfway still leaves a usable (and consistent) heap end
+    memory::with_vmm(|vmm| {
+        let mut added = 0;
+        while added < size {
+            let page = VirtAddr::new((heap_top + added) as u64);
+            if vmm.map_region(page, memory::PAGE_SIZE, HEAP_FLAGS).is_err() {
+                break;
+            }
+            added += memory::PAGE_SIZE;
+        }
+        added
+    })
+}
+
+// current heap size, including memory added by growing
+pub fn heap_size() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| ALLOCATOR.lock().size())
+}
 
-    init_heap(&mut mapper, &mut frame_allocator)
-        .expect("heap initialization failed");
+// memory::init has to be called first, heap pages are mapped through the virtual memory manager
+pub fn init() {
+    init_heap().expect("heap initialization failed");
 }
 
 // align needs to be power of 2
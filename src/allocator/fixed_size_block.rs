@@ -1,5 +1,6 @@
 use core::{alloc::{GlobalAlloc, Layout}, ptr::{self, NonNull}};
 use core::mem;
+use x86_64::instructions::interrupts;
 use super::Locked;
 
 // sizes must be power of 2 bcs they are also used as alignment (which must be powers of 2)
@@ -34,7 +35,24 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    pub fn size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // out of memory, ask for more pages after the heap end and retry
+        // padding by alignment, so that allocation fits even if the free space at the top is misaligned
+        let needed = layout.size() + layout.align();
+        let added = super::grow_heap(self.fallback_allocator.top(), needed);
+        if added == 0 {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_allocator.extend(added) };
+
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
@@ -43,8 +61,20 @@ impl FixedSizeBlockAllocator {
 }
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    // interrupts stay off while the heap is locked, so neither interrupted code nor a preempted thread
+    // can be holding the lock when an interrupt handler or the scheduler needs it
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.lock();
+        interrupts::without_interrupts(|| self.lock().allocate(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| unsafe { self.lock().deallocate(ptr, layout) })
+    }
+}
+
+impl FixedSizeBlockAllocator {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let allocator = self;
         match list_index(&layout) { // index of needed block size
             Some(index) => { // found block size
                 match allocator.list_heads[index].take() { // get first node
@@ -65,8 +95,8 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut allocator = self.lock();
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let allocator = self;
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
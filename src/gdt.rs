@@ -1,49 +1,96 @@
+use alloc::boxed::Box;
 use x86_64::VirtAddr;
 use x86_64::structures::{
     gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector},
     tss::TaskStateSegment,
 };
 use x86_64::instructions::{
+    interrupts,
     tables::load_tss,
-    segmentation::{CS, Segment},
+    segmentation::{CS, DS, ES, SS, Segment},
 };
 use lazy_static::lazy_static;
 
+use crate::memory::stack;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
-const STACK_SIZE: usize = 4096 * 5;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+// IST slots used by the IDT, they need a stack from the very beginning
+const BOOT_IST_INDEXES: [u16; 3] = [DOUBLE_FAULT_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX];
+const IST_STACK_PAGES: usize = 5;
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+
+// TSS has to stay writable, IST entries are swapped for guard-paged stacks once memory management is up
+// CPU only reads it when an interrupt switches stacks, so updating it after 'load_tss' is fine
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 // FUN FACT: GDT was used for segmentation on older architectures (modern x86 dont use segmentation though)
 lazy_static! { // we need to use GDT to load TSS structure
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss = &raw const TSS;
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*tss }));
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = { // TODO: need to implement some scalable stack creation
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE]; // allocate static stack
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            stack_start + STACK_SIZE // write top address bcs stacks on x86 grow downwards
-        };
-        tss
-    };
-}
-
 struct Selectors {
     code_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
 
 pub fn init() {
+    // exceptions can happen before heap and paging are set up, so until 'init_stacks' is called
+    // they use static stacks (without guard page, a nested overflow there would go unnoticed)
+    unsafe {
+        static mut BOOT_STACKS: [[u8; BOOT_STACK_SIZE]; BOOT_IST_INDEXES.len()] = [[0; BOOT_STACK_SIZE]; BOOT_IST_INDEXES.len()];
+        for (i, index) in BOOT_IST_INDEXES.into_iter().enumerate() {
+            let stack_start = VirtAddr::from_ptr(&raw const BOOT_STACKS[i]);
+            TSS.interrupt_stack_table[index as usize] = stack_start + BOOT_STACK_SIZE; // write top address bcs stacks on x86 grow downwards
+        }
+    }
+
     GDT.0.load(); // load actual GDT
     unsafe {
         CS::set_reg(GDT.1.code_selector); // reload 'cs' register
         load_tss(GDT.1.tss_selector); // load TSS
     }
-}
\ No newline at end of file
+}
+
+// gives every IST slot its own guard-paged stack, memory::init has to be called first
+pub fn init_stacks() {
+    for index in 0..7 {
+        // stack is never freed, TSS references it for the whole kernel lifetime
+        let stack = stack::allocate_stack(IST_STACK_PAGES).expect("failed to allocate IST stack");
+        interrupts::without_interrupts(|| unsafe {
+            TSS.interrupt_stack_table[index] = stack.top();
+        });
+    }
+}
+
+// own GDT and TSS (with fresh IST stacks) for an application processor, memory management has to be up
+// both are leaked, the CPU uses them until the machine resets
+pub fn init_ap() {
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    for index in 0..7 {
+        let stack = stack::allocate_stack(IST_STACK_PAGES).expect("failed to allocate IST stack");
+        tss.interrupt_stack_table[index] = stack.top();
+    }
+
+    let mut gdt = GlobalDescriptorTable::new();
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    let gdt = Box::leak(Box::new(gdt));
+
+    gdt.load();
+    unsafe {
+        CS::set_reg(code_selector);
+        // data segments still point into the startup GDT, null selectors are fine in 64-bit mode
+        SS::set_reg(SegmentSelector(0));
+        DS::set_reg(SegmentSelector(0));
+        ES::set_reg(SegmentSelector(0));
+        load_tss(tss_selector);
+    }
+}
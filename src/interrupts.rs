@@ -1,15 +1,17 @@
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
-use x86_64::{
-    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
-    instructions::port::Port,
-    registers::control::Cr2,
-};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
-use crate::{println, print, halt};
-use crate::gdt::DOUBLE_FAULT_IST_INDEX;
+
+pub mod apic;
+pub mod exceptions;
+pub mod irq;
+pub mod pit;
+
+pub use irq::{irq_count, register_irq, unregister_irq, IrqError};
 
 pub const PIC_1_OFFSET: u8 = 32; // 32 so it wont overlap with exception handler values
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -19,21 +21,18 @@ pub static PICS: Mutex<ChainedPics> = Mutex::new(unsafe {
     ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
 });
 
+static APIC_ACTIVE: AtomicBool = AtomicBool::new(false); // set once PICs are masked and I/O APIC took over
+
 lazy_static! { // IDT is a table with addresses to functions that CPU should execute when it encounters an exception
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new(); // each exception has it's own entry
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        unsafe {
-            idt.double_fault.set_handler_fn(double_fault_handler)
-                .set_stack_index(DOUBLE_FAULT_IST_INDEX); // needed in case of stack overflow, handler should use fresh stack
-        }
-        
-         // CPU reacts identically to exceptions and external interrupts
-        idt[InterruptIndex::Timer.as_usize()] // InterruptDescriptorTable implements IndexMut so array indexing syntax works
-            .set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler);
+        exceptions::set_handlers(&mut idt);
+
+        irq::set_handlers(&mut idt); // CPU reacts identically to exceptions and external interrupts
+        idt[InterruptIndex::ApicTimer.as_usize()]
+            .set_handler_fn(apic_timer_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR as usize]
+            .set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
@@ -43,11 +42,11 @@ lazy_static! { // IDT is a table with addresses to functions that CPU should exe
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET, // programmable interval timer
     Keyboard, // defaults to previous value +1
-
+    ApicTimer = PIC_2_OFFSET + 8, // local APIC timer, right after the legacy IRQ range
 }
 
 impl InterruptIndex {
-    const fn as_u8(self) -> u8 {
+    pub const fn as_u8(self) -> u8 {
         self as u8
     }
 
@@ -60,57 +59,80 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(
-    stack_frame: InterruptStackFrame)
-{    
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptController {
+    Pic8259,
+    Apic,
 }
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode
-) {
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    halt()
+pub fn controller() -> InterruptController {
+    match APIC_ACTIVE.load(Ordering::Acquire) {
+        true => InterruptController::Apic,
+        false => InterruptController::Pic8259,
+    }
 }
 
-// the most important exception handler - kind of catch all - prevents triple fault
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame, _error_code: u64) -> !
-{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+// switches from 8259 PICs to local APIC + I/O APIC when the machine has them, PICs stay in use otherwise
+// needs memory, heap and acpi initialized (MMIO registers are mapped through the VMM)
+pub fn init_apic() -> InterruptController {
+    let result = x86_64::instructions::interrupts::without_interrupts(|| -> Result<(), apic::ApicError> {
+        apic::init()?;
+        unsafe { PICS.lock().disable() }; // masks every line, legacy IRQs come through I/O APIC from now on
+        APIC_ACTIVE.store(true, Ordering::Release);
+        irq::unmask_registered();
+        apic::start_periodic_timer(InterruptIndex::ApicTimer.as_u8(), apic::TIMER_FREQUENCY);
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => {
+            let id = apic::local_apic().map_or(0, |local_apic| local_apic.id());
+            log::info!("interrupt controller: APIC (local APIC id {}, timer at {} Hz)", id, apic::TIMER_FREQUENCY);
+        }
+        Err(err) => log::warn!("interrupt controller: 8259 PIC (APIC unavailable: {:?})", err),
+    }
+    controller()
+}
+
+// IDT and local APIC of an application processor, its timer wakes the CPU up like on the bootstrap processor
+// legacy IRQs stay routed to the bootstrap processor
+pub fn init_ap() {
+    IDT.load();
+    if controller() == InterruptController::Apic && apic::init_ap() {
+        apic::start_periodic_timer(InterruptIndex::ApicTimer.as_u8(), apic::TIMER_FREQUENCY);
+    }
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(
+extern "x86-interrupt" fn apic_timer_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    //print!(".");
-    
-    end_of_interrupt(InterruptIndex::Timer);
+    apic::timer_tick();
+    end_of_interrupt(InterruptIndex::ApicTimer.as_u8());
+    crate::thread::preempt(); // only source of preemption on application processors
 }
 
-extern "x86-interrupt" fn keyboard_interrupt_handler(
+// raised when an interrupt disappears before CPU acknowledges it, must not be acknowledged with EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    let mut port = Port::new(0x60); // data port of the keyboard controller (PS/2)
-    let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
-
-    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
-fn end_of_interrupt(interrupt_id: InterruptIndex) {
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(interrupt_id.as_u8());
+fn end_of_interrupt(vector: u8) {
+    match controller() {
+        InterruptController::Apic => apic::end_of_interrupt(),
+        InterruptController::Pic8259 => unsafe {
+            PICS.lock()
+                .notify_end_of_interrupt(vector);
+        },
     }
 }
 
 #[test_case]
 fn test_breakpoint_exception() {
+    use exceptions::{exception_count, Exception};
+
+    let before = exception_count(Exception::Breakpoint);
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
+    assert_eq!(exception_count(Exception::Breakpoint), before + 1);
 }
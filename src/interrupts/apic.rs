@@ -0,0 +1,294 @@
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::__cpuid;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{registers::model_specific::Msr, VirtAddr};
+
+use super::pit;
+use crate::{acpi, memory};
+
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+pub const TIMER_FREQUENCY: u32 = 100; // default periodic rate of the local APIC timer (Hz)
+
+// local APIC registers (offsets from its base)
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TPR: usize = 0x80; // task priority, 0 accepts everything
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SVR: usize = 0xf0; // spurious vector, bit 8 software enables the APIC
+const LAPIC_ICR_LOW: usize = 0x300; // interrupt command register, writing the low half sends the IPI
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// I/O APIC is accessed indirectly, register number goes to IOREGSEL and its value is read/written through IOWIN
+const IOAPIC_IOWIN: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10; // two registers per entry
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APICS: OnceCell<Mutex<Vec<IoApic>>> = OnceCell::uninit();
+
+static TICKS_PER_MS: AtomicU32 = AtomicU32::new(0); // local APIC timer ticks (after divider) per millisecond
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    Unsupported, // CPUID doesn't report an on-chip APIC
+    NoMadt,      // ACPI tables missing, so there's no way to find the I/O APIC
+    NoIoApic,
+    MapFailed,
+}
+
+// registers are memory mapped and every CPU sees its own local APIC at the same address, so it's shared freely
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg as u64).as_ptr()) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg as u64).as_mut_ptr(), value) }
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(LAPIC_ID) >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    fn enable(&self) {
+        self.write(LAPIC_TPR, 0);
+        self.write(LAPIC_SVR, self.read(LAPIC_SVR) | 1 << 8 | SPURIOUS_VECTOR as u32);
+    }
+
+    fn set_timer(&self, lvt: u32, initial_count: u32) {
+        self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LAPIC_LVT_TIMER, lvt);
+        self.write(LAPIC_TIMER_INITIAL, initial_count); // writing initial count starts the timer
+    }
+
+    pub fn timer_remaining(&self) -> u32 {
+        self.read(LAPIC_TIMER_CURRENT)
+    }
+
+    // resets the processor with 'apic_id', it then waits for a startup IPI
+    pub fn send_init(&self, apic_id: u8) {
+        self.send_ipi(apic_id, ICR_INIT | ICR_ASSERT);
+    }
+
+    // processor waiting after INIT starts executing real mode code at 'page' * 4 KiB (CS = page << 8, IP = 0)
+    pub fn send_startup(&self, apic_id: u8, page: u8) {
+        self.send_ipi(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32);
+    }
+
+    fn send_ipi(&self, apic_id: u8, command: u32) {
+        self.write(LAPIC_ICR_HIGH, (apic_id as u32) << 24);
+        self.write(LAPIC_ICR_LOW, command);
+        while self.read(LAPIC_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&mut self, reg: u32) -> u32 {
+        unsafe {
+            write_volatile(self.base.as_mut_ptr(), reg);
+            read_volatile((self.base + IOAPIC_IOWIN as u64).as_ptr())
+        }
+    }
+
+    fn write(&mut self, reg: u32, value: u32) {
+        unsafe {
+            write_volatile(self.base.as_mut_ptr(), reg);
+            write_volatile((self.base + IOAPIC_IOWIN as u64).as_mut_ptr(), value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    fn set_entry(&mut self, gsi: u32, entry: u64) {
+        let reg = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        self.write(reg, REDIRECTION_MASKED as u32); // mask while the entry is half written
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+}
+
+pub fn is_supported() -> bool {
+    __cpuid(1).edx & (1 << 9) != 0
+}
+
+// None until 'init' succeeded
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try_get().ok()
+}
+
+// sets up local APIC and all I/O APICs found in the MADT, every redirection entry starts masked
+// has to be called with interrupts disabled, after memory, heap and acpi init
+pub(super) fn init() -> Result<(), ApicError> {
+    if !is_supported() {
+        return Err(ApicError::Unsupported);
+    }
+    let info = acpi::info().ok_or(ApicError::NoMadt)?;
+    if info.io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let local_base = memory::with_vmm(|vmm| vmm.map_mmio(info.local_apic_address, memory::PAGE_SIZE))
+        .map_err(|_| ApicError::MapFailed)?;
+    let mut io_apics = Vec::new();
+    for entry in &info.io_apics {
+        let base = memory::with_vmm(|vmm| vmm.map_mmio(entry.address, memory::PAGE_SIZE))
+            .map_err(|_| ApicError::MapFailed)?;
+        let mut io_apic = IoApic { base, gsi_base: entry.gsi_base, entries: 0 };
+        io_apic.entries = (io_apic.read(IOAPIC_VERSION) >> 16 & 0xff) + 1; // bits 16-23 hold max entry index
+        for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+            io_apic.set_entry(gsi, REDIRECTION_MASKED);
+        }
+        io_apics.push(io_apic);
+    }
+
+    enable_globally();
+    let local_apic = LOCAL_APIC.get_or_init(|| LocalApic { base: local_base });
+    IO_APICS.init_once(|| Mutex::new(io_apics));
+    local_apic.enable();
+    calibrate_timer(local_apic);
+
+    Ok(())
+}
+
+// enables local APIC of an application processor, every processor sees its own one at the same address
+// so it only works after 'init' ran on the bootstrap processor
+pub(super) fn init_ap() -> bool {
+    match local_apic() {
+        Some(local_apic) => {
+            enable_globally();
+            local_apic.enable();
+            true
+        }
+        None => false,
+    }
+}
+
+fn enable_globally() {
+    unsafe {
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        let value = apic_base.read();
+        apic_base.write(value | APIC_GLOBAL_ENABLE); // firmware usually leaves it on, but make sure
+    }
+}
+
+// routes legacy ISA IRQ to 'vector' on the bootstrap processor, applying MADT overrides (e.g. IRQ0 -> GSI 2)
+pub fn route_irq(irq: u8, vector: u8) -> bool {
+    set_irq_entry(irq, vector as u64)
+}
+
+pub fn mask_irq(irq: u8) -> bool {
+    set_irq_entry(irq, REDIRECTION_MASKED)
+}
+
+fn set_irq_entry(irq: u8, entry: u64) -> bool {
+    let (Some(info), Some(local_apic), Ok(io_apics)) = (acpi::info(), local_apic(), IO_APICS.try_get()) else {
+        return false;
+    };
+    let (gsi, flags) = info.irq_to_gsi(irq);
+
+    let mut entry = entry | (local_apic.id() as u64) << 56; // fixed delivery, physical destination
+    if flags & 0b11 == 0b11 {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if flags >> 2 & 0b11 == 0b11 {
+        entry |= REDIRECTION_LEVEL_TRIGGERED;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut io_apics = io_apics.lock();
+        match io_apics.iter_mut().find(|io_apic| io_apic.handles(gsi)) {
+            Some(io_apic) => {
+                io_apic.set_entry(gsi, entry);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+// counts how far the local APIC timer gets in 10ms measured by PIT, its frequency depends on the CPU bus
+fn calibrate_timer(local_apic: &LocalApic) {
+    local_apic.set_timer(LVT_MASKED, u32::MAX);
+    pit::sleep_ms(10);
+    let elapsed = u32::MAX - local_apic.timer_remaining();
+    local_apic.set_timer(LVT_MASKED, 0); // stop
+    TICKS_PER_MS.store((elapsed / 10).max(1), Ordering::Relaxed);
+}
+
+// fires 'vector' 'hz' times per second until stopped
+pub fn start_periodic_timer(vector: u8, hz: u32) {
+    if let Some(local_apic) = local_apic() {
+        let count = TICKS_PER_MS.load(Ordering::Relaxed) as u64 * 1000 / hz as u64;
+        local_apic.set_timer(LVT_PERIODIC | vector as u32, count.clamp(1, u32::MAX as u64) as u32);
+    }
+}
+
+// fires 'vector' once after 'micros' microseconds
+pub fn start_one_shot_timer(vector: u8, micros: u64) {
+    if let Some(local_apic) = local_apic() {
+        let count = TICKS_PER_MS.load(Ordering::Relaxed) as u64 * micros / 1000;
+        local_apic.set_timer(vector as u32, count.clamp(1, u32::MAX as u64) as u32);
+    }
+}
+
+pub fn stop_timer() {
+    if let Some(local_apic) = local_apic() {
+        local_apic.set_timer(LVT_MASKED, 0);
+    }
+}
+
+// number of local APIC timer interrupts handled so far
+pub fn timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+pub(super) fn timer_tick() {
+    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(super) fn end_of_interrupt() {
+    if let Some(local_apic) = local_apic() {
+        local_apic.end_of_interrupt();
+    }
+}
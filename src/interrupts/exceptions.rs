@@ -0,0 +1,305 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{
+        InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode, SelectorErrorCode,
+    },
+    VirtAddr,
+};
+
+use crate::{memory, output, println, serial_println};
+use crate::gdt::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+
+// architectural exception vectors (0-31), gaps are reserved by Intel/AMD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    DivideError = 0,
+    Debug,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    DoubleFault,
+    InvalidTss = 10,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    PageFault,
+    X87FloatingPoint = 16,
+    AlignmentCheck,
+    MachineCheck,
+    SimdFloatingPoint,
+    Virtualization,
+    ControlProtection,
+    HvInjection = 28,
+    VmmCommunication,
+    Security,
+}
+
+impl Exception {
+    pub const fn vector(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Exception::DivideError => "DIVIDE ERROR",
+            Exception::Debug => "DEBUG",
+            Exception::NonMaskableInterrupt => "NON-MASKABLE INTERRUPT",
+            Exception::Breakpoint => "BREAKPOINT",
+            Exception::Overflow => "OVERFLOW",
+            Exception::BoundRangeExceeded => "BOUND RANGE EXCEEDED",
+            Exception::InvalidOpcode => "INVALID OPCODE",
+            Exception::DeviceNotAvailable => "DEVICE NOT AVAILABLE",
+            Exception::DoubleFault => "DOUBLE FAULT",
+            Exception::InvalidTss => "INVALID TSS",
+            Exception::SegmentNotPresent => "SEGMENT NOT PRESENT",
+            Exception::StackSegmentFault => "STACK-SEGMENT FAULT",
+            Exception::GeneralProtectionFault => "GENERAL PROTECTION FAULT",
+            Exception::PageFault => "PAGE FAULT",
+            Exception::X87FloatingPoint => "X87 FLOATING-POINT EXCEPTION",
+            Exception::AlignmentCheck => "ALIGNMENT CHECK",
+            Exception::MachineCheck => "MACHINE CHECK",
+            Exception::SimdFloatingPoint => "SIMD FLOATING-POINT EXCEPTION",
+            Exception::Virtualization => "VIRTUALIZATION EXCEPTION",
+            Exception::ControlProtection => "CONTROL PROTECTION EXCEPTION",
+            Exception::HvInjection => "HYPERVISOR INJECTION EXCEPTION",
+            Exception::VmmCommunication => "VMM COMMUNICATION EXCEPTION",
+            Exception::Security => "SECURITY EXCEPTION",
+        }
+    }
+
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Exception::DivideError => "#DE",
+            Exception::Debug => "#DB",
+            Exception::NonMaskableInterrupt => "NMI",
+            Exception::Breakpoint => "#BP",
+            Exception::Overflow => "#OF",
+            Exception::BoundRangeExceeded => "#BR",
+            Exception::InvalidOpcode => "#UD",
+            Exception::DeviceNotAvailable => "#NM",
+            Exception::DoubleFault => "#DF",
+            Exception::InvalidTss => "#TS",
+            Exception::SegmentNotPresent => "#NP",
+            Exception::StackSegmentFault => "#SS",
+            Exception::GeneralProtectionFault => "#GP",
+            Exception::PageFault => "#PF",
+            Exception::X87FloatingPoint => "#MF",
+            Exception::AlignmentCheck => "#AC",
+            Exception::MachineCheck => "#MC",
+            Exception::SimdFloatingPoint => "#XM",
+            Exception::Virtualization => "#VE",
+            Exception::ControlProtection => "#CP",
+            Exception::HvInjection => "#HV",
+            Exception::VmmCommunication => "#VC",
+            Exception::Security => "#SX",
+        }
+    }
+}
+
+// everything the CPU tells us about an exception, printed the same way for every vector
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionReport {
+    pub exception: Exception,
+    pub stack_frame: InterruptStackFrameValue,
+    pub error_code: Option<u64>,
+    pub cr2: Option<VirtAddr>, // only set for page faults, it holds the accessed address
+    pub region: Option<&'static str>, // registered memory region the faulting address belongs to
+}
+
+impl ExceptionReport {
+    fn new(exception: Exception, stack_frame: &InterruptStackFrame, error_code: Option<u64>) -> Self {
+        let cr2 = match exception {
+            Exception::PageFault => Some(Cr2::read()),
+            _ => None,
+        };
+        ExceptionReport { exception, stack_frame: **stack_frame, error_code, cr2, region: None }
+    }
+
+    fn fmt_error_code(&self, f: &mut fmt::Formatter, code: u64) -> fmt::Result {
+        write!(f, "    ERROR CODE: {:#x}", code)?;
+        match self.exception {
+            // these push a selector error code, zero means fault wasn't caused by a segment load
+            Exception::InvalidTss | Exception::SegmentNotPresent
+            | Exception::StackSegmentFault | Exception::GeneralProtectionFault if code != 0 => {
+                let selector = SelectorErrorCode::new_truncate(code);
+                write!(f, " (external: {}, table: {:?}, index: {})",
+                    selector.external(), selector.descriptor_table(), selector.index())?;
+            }
+            Exception::PageFault => {
+                write!(f, " ({:?})", PageFaultErrorCode::from_bits_truncate(code))?;
+            }
+            Exception::ControlProtection => {
+                let cause = match code & 0x7fff {
+                    1 => "near ret",
+                    2 => "far ret/iret",
+                    3 => "missing endbranch",
+                    4 => "rstorssp",
+                    5 => "setssbsy",
+                    _ => "unknown",
+                };
+                write!(f, " ({})", cause)?;
+            }
+            _ => {}
+        }
+        writeln!(f)
+    }
+}
+
+impl fmt::Display for ExceptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = &self.stack_frame;
+        writeln!(f, "EXCEPTION: {} ({}, vector {})",
+            self.exception.name(), self.exception.mnemonic(), self.exception.vector())?;
+        writeln!(f, "    RIP: {:#018x}  CS: {:#06x}  RFLAGS: {:#010x}",
+            frame.instruction_pointer.as_u64(), frame.code_segment, frame.cpu_flags)?;
+        writeln!(f, "    RSP: {:#018x}  SS: {:#06x}", frame.stack_pointer.as_u64(), frame.stack_segment)?;
+        if let Some(code) = self.error_code {
+            self.fmt_error_code(f, code)?;
+        }
+        if let Some(addr) = self.cr2 {
+            writeln!(f, "    CR2: {:#018x}", addr.as_u64())?;
+        }
+        if let Some(region) = self.region {
+            writeln!(f, "    REGION: {}", region)?;
+        }
+        Ok(())
+    }
+}
+
+static EXCEPTION_COUNTS: [AtomicU64; 32] = [const { AtomicU64::new(0) }; 32];
+// called instead of panicking on fatal exceptions, integration tests use it to check which exception fired
+static FATAL_HOOK: spin::Once<fn(&ExceptionReport) -> !> = spin::Once::new();
+
+// number of times given exception was raised since boot
+pub fn exception_count(exception: Exception) -> u64 {
+    EXCEPTION_COUNTS[exception.vector() as usize].load(Ordering::Relaxed)
+}
+
+pub fn set_fatal_hook(hook: fn(&ExceptionReport) -> !) {
+    FATAL_HOOK.call_once(|| hook);
+}
+
+// prints report on both VGA and serial console
+fn report(report: &ExceptionReport) {
+    EXCEPTION_COUNTS[report.exception.vector() as usize].fetch_add(1, Ordering::Relaxed);
+    println!("{}", report);
+    serial_println!("{}", report);
+}
+
+fn fatal(exception_report: ExceptionReport) -> ! {
+    output::panic_mode(); // the exception might have hit while this CPU was printing
+    report(&exception_report);
+    if let Some(hook) = FATAL_HOOK.r#try() {
+        hook(&exception_report);
+    }
+    panic!("unrecoverable {} ({})", exception_report.exception.name(), exception_report.exception.mnemonic());
+}
+
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception.set_handler_fn(control_protection_handler);
+    idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_handler);
+    unsafe { // these can hit at any point (also on a broken stack), so they get fresh stacks
+        idt.double_fault.set_handler_fn(double_fault_handler)
+            .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(NMI_IST_INDEX);
+        idt.machine_check.set_handler_fn(machine_check_handler)
+            .set_stack_index(MACHINE_CHECK_IST_INDEX);
+    }
+}
+
+// generates handlers that print the report and never return
+macro_rules! fatal_handler {
+    ($name:ident, $exception:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+            fatal(ExceptionReport::new($exception, &stack_frame, None));
+        }
+    };
+    ($name:ident, $exception:expr, error_code) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+            fatal(ExceptionReport::new($exception, &stack_frame, Some(error_code)));
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, Exception::DivideError);
+fatal_handler!(bound_range_exceeded_handler, Exception::BoundRangeExceeded);
+fatal_handler!(invalid_opcode_handler, Exception::InvalidOpcode);
+fatal_handler!(device_not_available_handler, Exception::DeviceNotAvailable);
+fatal_handler!(invalid_tss_handler, Exception::InvalidTss, error_code);
+fatal_handler!(segment_not_present_handler, Exception::SegmentNotPresent, error_code);
+fatal_handler!(stack_segment_fault_handler, Exception::StackSegmentFault, error_code);
+fatal_handler!(general_protection_fault_handler, Exception::GeneralProtectionFault, error_code);
+fatal_handler!(x87_floating_point_handler, Exception::X87FloatingPoint);
+fatal_handler!(alignment_check_handler, Exception::AlignmentCheck, error_code);
+fatal_handler!(simd_floating_point_handler, Exception::SimdFloatingPoint);
+fatal_handler!(virtualization_handler, Exception::Virtualization);
+fatal_handler!(control_protection_handler, Exception::ControlProtection, error_code);
+fatal_handler!(hv_injection_handler, Exception::HvInjection);
+fatal_handler!(vmm_communication_handler, Exception::VmmCommunication, error_code);
+fatal_handler!(security_handler, Exception::Security, error_code);
+
+// traps, execution can safely continue after the reporting instruction
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    report(&ExceptionReport::new(Exception::Debug, &stack_frame, None));
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    report(&ExceptionReport::new(Exception::Breakpoint, &stack_frame, None));
+}
+
+extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    report(&ExceptionReport::new(Exception::Overflow, &stack_frame, None));
+}
+
+// NMIs are used by hardware to signal non-fatal events too (watchdogs, profiling), so just report it
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    report(&ExceptionReport::new(Exception::NonMaskableInterrupt, &stack_frame, None));
+}
+
+// faults inside registered regions (lazy, copy-on-write, ...) are resolved by their owner and the access is retried
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode
+) {
+    if let Err(region) = memory::fault::handle_page_fault(Cr2::read(), error_code) {
+        let mut report = ExceptionReport::new(Exception::PageFault, &stack_frame, Some(error_code.bits()));
+        report.region = region;
+        fatal(report);
+    }
+}
+
+// the most important exception handler - kind of catch all - prevents triple fault
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame, error_code: u64) -> !
+{
+    fatal(ExceptionReport::new(Exception::DoubleFault, &stack_frame, Some(error_code)));
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    fatal(ExceptionReport::new(Exception::MachineCheck, &stack_frame, None));
+}
@@ -0,0 +1,125 @@
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use super::{apic, controller, end_of_interrupt, InterruptController, PICS, PIC_1_OFFSET};
+
+// legacy ISA lines, IRQ n is delivered on vector PIC_1_OFFSET + n by both PICs and I/O APIC
+pub const IRQ_COUNT: usize = 16;
+
+// fn pointers are stored as raw pointers, so the table can be read from interrupt context without locking
+static HANDLERS: [AtomicPtr<()>; IRQ_COUNT] = [const { AtomicPtr::new(ptr::null_mut()) }; IRQ_COUNT];
+static COUNTS: [AtomicU64; IRQ_COUNT] = [const { AtomicU64::new(0) }; IRQ_COUNT];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidIrq,
+    AlreadyRegistered,
+}
+
+// one stub per line, every stub does the same: count, call registered handler, acknowledge
+macro_rules! irq_stubs {
+    ($($irq:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        const STUBS: [extern "x86-interrupt" fn(InterruptStackFrame); IRQ_COUNT] = [$($name),*];
+    };
+}
+
+irq_stubs! {
+    0 => irq0, 1 => irq1, 2 => irq2, 3 => irq3, 4 => irq4, 5 => irq5, 6 => irq6, 7 => irq7,
+    8 => irq8, 9 => irq9, 10 => irq10, 11 => irq11, 12 => irq12, 13 => irq13, 14 => irq14, 15 => irq15,
+}
+
+pub const fn vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    for (irq, stub) in STUBS.iter().enumerate() {
+        idt[vector(irq as u8) as usize].set_handler_fn(*stub);
+    }
+}
+
+fn dispatch(irq: u8) {
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    // acknowledged first, so a handler switching threads (the timer's tick hook) doesn't hold up the line meanwhile
+    // ISA lines are edge triggered, one raised again while the handler runs is delivered once it returns
+    end_of_interrupt(vector(irq)); // handlers never have to acknowledge themselves
+    if let Some(handler) = handler(irq) {
+        handler();
+    }
+}
+
+fn handler(irq: u8) -> Option<fn()> {
+    let handler = HANDLERS[irq as usize].load(Ordering::Acquire);
+    // only ever stored from a 'fn()' in 'register_irq'
+    (!handler.is_null()).then(|| unsafe { mem::transmute::<*mut (), fn()>(handler) })
+}
+
+// installs 'handler' for the line and unmasks it on whichever controller is active
+// handler runs in interrupt context, so it must not allocate or block
+pub fn register_irq(irq: u8, handler: fn()) -> Result<(), IrqError> {
+    let slot = HANDLERS.get(irq as usize).ok_or(IrqError::InvalidIrq)?;
+    slot.compare_exchange(ptr::null_mut(), handler as *mut (), Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| IrqError::AlreadyRegistered)?;
+    unmask(irq);
+    Ok(())
+}
+
+// masks the line and removes its handler, returns the handler if there was one
+pub fn unregister_irq(irq: u8) -> Option<fn()> {
+    let slot = HANDLERS.get(irq as usize)?;
+    mask(irq);
+    let handler = slot.swap(ptr::null_mut(), Ordering::AcqRel);
+    (!handler.is_null()).then(|| unsafe { mem::transmute::<*mut (), fn()>(handler) })
+}
+
+// number of interrupts received on the line (including ones without a handler)
+pub fn irq_count(irq: u8) -> u64 {
+    COUNTS.get(irq as usize).map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+// called after switching controllers, so lines registered earlier keep working
+pub(super) fn unmask_registered() {
+    (0..IRQ_COUNT as u8).filter(|&irq| handler(irq).is_some()).for_each(unmask);
+}
+
+fn unmask(irq: u8) {
+    match controller() {
+        InterruptController::Apic => {
+            apic::route_irq(irq, vector(irq));
+        }
+        InterruptController::Pic8259 => update_pic_masks(|masks| {
+            masks[irq as usize / 8] &= !(1 << (irq % 8));
+            if irq >= 8 {
+                masks[0] &= !(1 << 2); // secondary PIC is cascaded through IRQ2
+            }
+        }),
+    }
+}
+
+fn mask(irq: u8) {
+    match controller() {
+        InterruptController::Apic => {
+            apic::mask_irq(irq);
+        }
+        InterruptController::Pic8259 => update_pic_masks(|masks| masks[irq as usize / 8] |= 1 << (irq % 8)),
+    }
+}
+
+fn update_pic_masks(f: impl FnOnce(&mut [u8; 2])) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        unsafe {
+            let mut masks = pics.read_masks();
+            f(&mut masks);
+            pics.write_masks(masks[0], masks[1]);
+        }
+    });
+}
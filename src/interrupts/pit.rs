@@ -0,0 +1,54 @@
+use x86_64::instructions::port::Port;
+
+// 8253/8254 programmable interval timer, input clock is the same on every PC
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_DATA: u16 = 0x40; // wired to IRQ0
+const CHANNEL_2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+const CHANNEL_2_GATE: u16 = 0x61; // bit 0 gate, bit 1 speaker, bit 5 channel 2 output
+
+// makes channel 0 fire IRQ0 periodically, returns the divisor actually used (rate is PIT_FREQUENCY / divisor)
+pub fn set_frequency(hz: u32) -> u16 {
+    let divisor = (PIT_FREQUENCY / hz).clamp(1, u16::MAX as u32) as u16;
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL_0_DATA);
+
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        command.write(0b0011_0100); // channel 0, lobyte/hibyte, mode 2 (rate generator), binary
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    });
+    divisor
+}
+
+// busy waits 'ms' milliseconds by polling channel 2 (no interrupts needed), used for calibrating other timers
+// single countdown is 16 bit, so longer waits are split into chunks
+pub fn sleep_ms(ms: u32) {
+    for _ in 0..ms / 50 {
+        countdown(PIT_FREQUENCY / 20);
+    }
+    let rest = ms % 50;
+    if rest > 0 {
+        countdown(PIT_FREQUENCY * rest / 1000);
+    }
+}
+
+fn countdown(count: u32) {
+    let mut gate: Port<u8> = Port::new(CHANNEL_2_GATE);
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL_2_DATA);
+
+    unsafe {
+        let value = gate.read() & !0b11; // gate low, speaker off
+        gate.write(value);
+        command.write(0b1011_0000); // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count), binary
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+        gate.write(value | 1); // rising edge on gate starts counting
+
+        while gate.read() & (1 << 5) == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
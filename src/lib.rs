@@ -6,13 +6,20 @@
 #![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
+pub mod acpi;
 pub mod allocator;
 pub mod gdt;
 pub mod interrupts;
+pub mod logger;
 pub mod memory;
+pub mod output;
+pub mod ps2;
 pub mod serial;
+pub mod smp;
 pub mod vga_buffer;
 pub mod task;
+pub mod thread;
+pub mod time;
 pub mod test_utils;
 
 #[cfg(test)]
@@ -27,6 +34,9 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::register_irq(1, task::keyboard::keyboard_interrupt).expect("keyboard IRQ already taken");
+    serial::init();
+    time::init();
     x86_64::instructions::interrupts::enable(); // executes 'sti' instruction (set interrupts)
 }
 
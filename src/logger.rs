@@ -0,0 +1,228 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use log::{LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{serial, smp, time, vga_buffer};
+
+const RING_SIZE: usize = 16 * 1024; // bytes of formatted records kept for 'dmesg'
+const NO_OWNER: usize = usize::MAX;
+
+static LOGGER: KernelLogger = KernelLogger;
+// level of modules without a filter of their own, an atomic so it can always be read
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Info as u8);
+// only locked with interrupts disabled, so interrupt handlers can take it too
+static FILTERS: Mutex<Filters> = Mutex::new(Filters { modules: Vec::new() });
+// CPU holding FILTERS, a record logged while this CPU changes them (e.g. from an NMI) uses the default level
+static FILTERS_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+// most verbose level each sink still shows, indexed by 'Sink'
+static SINK_LEVELS: [AtomicU8; Sink::COUNT] = [const { AtomicU8::new(LevelFilter::Trace as u8) }; Sink::COUNT];
+static RING: Mutex<RingBuffer> = Mutex::new(RingBuffer::new());
+// CPU holding RING, a record logged while this CPU is writing one (e.g. from an NMI) skips the ring
+static RING_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+// where records go, every record passing the filters is written to each sink whose level allows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Vga,
+    Serial, // log port, see 'serial::set_log_port'
+    Memory, // ring buffer read by 'dmesg'
+}
+
+impl Sink {
+    const COUNT: usize = 3;
+}
+
+struct Filters {
+    modules: Vec<(String, LevelFilter)>, // module path prefixes, the longest matching one wins
+}
+
+impl Filters {
+    fn level(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target.strip_prefix(module.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(default, |&(_, level)| level)
+    }
+
+    // lets the log macros skip records no filter can let through without calling the logger
+    fn update_max_level(&self) {
+        let max = self.modules.iter().map(|&(_, level)| level).fold(default_level(), Ord::max);
+        log::set_max_level(max);
+    }
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level_for(metadata.target())
+    }
+
+    // never allocates, so it works from interrupt handlers
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let uptime = time::uptime();
+        let line = format_args!(
+            "[{:>5}.{:06}] {:<5} {}: {}\n",
+            uptime.as_secs(),
+            uptime.subsec_micros(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if record.level() <= sink_level(Sink::Memory) {
+            write_ring(line);
+        }
+        if record.level() <= sink_level(Sink::Serial) {
+            serial::_print(line);
+        }
+        if record.level() <= sink_level(Sink::Vga) {
+            vga_buffer::_print(line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// installs the kernel logger for the 'log' macros, records more verbose than 'level' are dropped
+// calling it again only changes the default level
+pub fn init(level: LevelFilter) {
+    let _ = log::set_logger(&LOGGER);
+    set_default_level(level);
+}
+
+// level of modules without a filter of their own
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as u8, Ordering::Relaxed);
+    change_filters(|filters| filters.update_max_level());
+}
+
+pub fn default_level() -> LevelFilter {
+    level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed))
+}
+
+// applies to 'module' and everything below it (e.g. "ruost::task" also covers "ruost::task::keyboard")
+pub fn set_module_level(module: &str, level: LevelFilter) {
+    change_filters(|filters| {
+        match filters.modules.iter_mut().find(|(prefix, _)| prefix == module) {
+            Some(filter) => filter.1 = level,
+            None => filters.modules.push((String::from(module), level)),
+        }
+        filters.update_max_level();
+    });
+}
+
+pub fn clear_module_level(module: &str) {
+    change_filters(|filters| {
+        filters.modules.retain(|(prefix, _)| prefix != module);
+        filters.update_max_level();
+    });
+}
+
+// level a record from 'target' needs to be logged
+// the default level while this CPU is changing the filters, so logging from an NMI or fault never deadlocks on them
+pub fn level_for(target: &str) -> LevelFilter {
+    with_filters(|filters| filters.level(target, default_level())).unwrap_or_else(default_level)
+}
+
+fn change_filters(f: impl FnOnce(&mut Filters)) {
+    with_filters(f).expect("log filters changed while this CPU is changing them");
+}
+
+// None when this CPU already holds the filters
+fn with_filters<R>(f: impl FnOnce(&mut Filters) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let cpu = smp::cpu_id();
+        if FILTERS_OWNER.load(Ordering::Acquire) == cpu {
+            return None;
+        }
+        let mut filters = FILTERS.lock();
+        FILTERS_OWNER.store(cpu, Ordering::Release);
+        let result = f(&mut filters);
+        FILTERS_OWNER.store(NO_OWNER, Ordering::Release);
+        Some(result)
+    })
+}
+
+pub fn set_sink_level(sink: Sink, level: LevelFilter) {
+    SINK_LEVELS[sink as usize].store(level as u8, Ordering::Relaxed);
+}
+
+pub fn sink_level(sink: Sink) -> LevelFilter {
+    level_filter(SINK_LEVELS[sink as usize].load(Ordering::Relaxed))
+}
+
+// levels are kept as u8 in atomics
+fn level_filter(level: u8) -> LevelFilter {
+    LevelFilter::iter().find(|filter| *filter as u8 == level).unwrap_or(LevelFilter::Trace)
+}
+
+// records kept in memory, oldest first, records partially overwritten by newer ones are left out
+pub fn dmesg() -> String {
+    let bytes = interrupts::without_interrupts(|| RING.lock().contents());
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn write_ring(line: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let cpu = smp::cpu_id();
+        if RING_OWNER.load(Ordering::Acquire) == cpu {
+            return;
+        }
+        let mut ring = RING.lock();
+        RING_OWNER.store(cpu, Ordering::Release);
+        let _ = ring.write_fmt(line);
+        RING_OWNER.store(NO_OWNER, Ordering::Release);
+    });
+}
+
+// fixed size, so writing to it never allocates
+struct RingBuffer {
+    bytes: [u8; RING_SIZE],
+    start: usize, // oldest byte
+    len: usize,
+    overwritten: bool, // first line in the buffer might be cut off
+}
+
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer { bytes: [0; RING_SIZE], start: 0, len: 0, overwritten: false }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == RING_SIZE {
+            self.bytes[self.start] = byte;
+            self.start = (self.start + 1) % RING_SIZE;
+            self.overwritten = true;
+        } else {
+            self.bytes[(self.start + self.len) % RING_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..self.len).map(|i| self.bytes[(self.start + i) % RING_SIZE]).collect();
+        if self.overwritten {
+            let cut = bytes.iter().position(|&byte| byte == b'\n').map_or(bytes.len(), |end| end + 1);
+            bytes.drain(..cut);
+        }
+        bytes
+    }
+}
+
+impl Write for RingBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
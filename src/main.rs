@@ -7,7 +7,13 @@
 extern crate alloc;
 
 use core::panic::PanicInfo;
-use ruost::{halt, init, println};
+use core::fmt::Write;
+use log::LevelFilter;
+use ruost::{halt, init, logger, output, println};
+use ruost::serial::{self, ComPort, SerialConsole};
+use ruost::task::executor::{Spawner, TaskInfo};
+use ruost::task::keyboard::{self, Layout};
+use ruost::{smp, time};
 use bootloader::{BootInfo, entry_point};
 
 // enables type checking of the entry point function
@@ -16,24 +22,50 @@ entry_point!(kernel_main);
 // this function is the entry point - linker looks for a function named "_start" by default but entry_point macro handles that)
 fn kernel_main(boot_info: &'static BootInfo) -> ! { // passed by bootloader, needed to use mapped physical memory
     init();
+    logger::init(LevelFilter::Info);
     println!("Hejka{}", "!");
+    // logs stay on COM1, a second port (if attached) gets the interactive console
+    if serial::set_console_port(ComPort::Com2).is_ok() {
+        log::info!("serial console on COM2");
+    }
 
-    use ruost::allocator;
-    use ruost::task::keyboard;
+    use ruost::{acpi, allocator, gdt, interrupts, memory, thread};
+    use ruost::task::mouse;
     use ruost::task::executor::Executor;
-    use ruost::task::Task;
+    use ruost::task::{Priority, Task};
     
-    allocator::init(boot_info);
+    memory::init(boot_info);
+    allocator::init();
+    gdt::init_stacks();
+    thread::init();
+    output::start_flusher(); // printing from here on only copies into a per-CPU buffer
+    acpi::init();
+    interrupts::init_apic();
+    match mouse::init() {
+        Ok(kind) => log::info!("mouse: {:?}", kind),
+        Err(err) => log::warn!("no mouse ({:?})", err),
+    }
+    match smp::init() {
+        Ok(cpus) => log::info!("{} CPUs online", cpus),
+        Err(err) => log::warn!("running on bootstrap processor only ({:?})", err),
+    }
 
     #[cfg(test)]
     test_main();
     
     println!("Przeszlo!");
 
-    let mut executor = Executor::new(); // new
-    executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(keyboard::print_keypress()));
-    executor.run();
+    // executor is just one of the threads, others get preempted in between
+    let executor = thread::spawn(|| {
+        let mut executor = Executor::new(); // new
+        executor.spawn(Task::new(example_task()).with_name("example"));
+        executor.spawn(Task::new(keyboard::run()).with_name("keyboard").with_priority(Priority::High)); // input stays responsive
+        executor.spawn(Task::new(keyboard::print_keypress()).with_name("keypress"));
+        executor.spawn(Task::new(serial_shell(executor.spawner())).with_name("serial"));
+        smp::start_workers(&executor.spawner()); // other cores share the tasks
+        executor.run();
+    });
+    executor.join();
 
     halt()
 }
@@ -47,11 +79,55 @@ async fn example_task() {
     println!("async number: {}", number);
 }
 
+// commands read from the console port, lets the kernel be driven headlessly (e.g. 'qemu -serial stdio')
+async fn serial_shell(spawner: Spawner) {
+    let mut console = SerialConsole::new();
+    let _ = write!(console, "> ");
+    while let Some(line) = console.read_line().await {
+        let mut words = line.split_whitespace();
+        let _ = match (words.next(), words.next()) {
+            (None, _) => Ok(()),
+            (Some("help"), _) => writeln!(console, "commands: help, uptime, cpus, tasks, layout [name], dmesg, log <level> [module], echo <text>"),
+            (Some("uptime"), _) => writeln!(console, "{:?}", time::uptime()),
+            (Some("cpus"), _) => writeln!(console, "{}", smp::cpu_count()),
+            (Some("tasks"), _) => {
+                let _ = writeln!(console, "{}", TaskInfo::HEADER);
+                spawner.snapshot().iter().try_for_each(|task| writeln!(console, "{}", task))
+            }
+            (Some("layout"), None) => writeln!(console, "{}", keyboard::layout().name()),
+            (Some("layout"), Some(name)) => match Layout::from_name(name) {
+                Some(layout) => {
+                    keyboard::set_layout(layout);
+                    Ok(())
+                }
+                None => writeln!(console, "unknown layout, one of: {:?}", Layout::ALL.map(Layout::name)),
+            },
+            (Some("dmesg"), _) => write!(console, "{}", logger::dmesg()),
+            (Some("log"), Some(level)) => match (level.parse::<LevelFilter>(), words.next()) {
+                (Ok(level), Some(module)) => {
+                    logger::set_module_level(module, level);
+                    Ok(())
+                }
+                (Ok(level), None) => {
+                    logger::set_default_level(level);
+                    Ok(())
+                }
+                (Err(_), _) => writeln!(console, "unknown level, one of: off, error, warn, info, debug, trace"),
+            },
+            (Some("echo"), _) => writeln!(console, "{}", line.trim_start().trim_start_matches("echo").trim_start()),
+            (Some(command), _) => writeln!(console, "unknown command '{}', try 'help'", command),
+        };
+        let _ = write!(console, "> ");
+    }
+}
+
 // function called on panic, we have to specify that bcs we dont have panic handling that comes with std
 #[cfg(not(test))] // don't include in testing
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    output::panic_mode(); // the panic might have hit while this CPU was printing
     println!("{}", info);
+    ruost::serial_println!("{}", info);
     halt()
 }
 
@@ -1,56 +1,328 @@
+pub mod fault;
+pub mod frame_allocator;
+pub mod stack;
+
+use bootloader::BootInfo;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
 use x86_64::{
-    registers::control::Cr3, structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB}, PhysAddr, VirtAddr
+    instructions::interrupts,
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, TranslateResult, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+        Translate,
+    },
+    PhysAddr, VirtAddr,
 };
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
-use bootloader::BootInfo;
 
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+use crate::smp;
+use frame_allocator::BitmapFrameAllocator;
+
+pub const PAGE_SIZE: usize = 4096;
+
+// virtual address range handed out by 'reserve' (drivers, stacks, MMIO), far away from heap and physical memory mapping
+pub const KERNEL_SPACE_START: u64 = 0x_5555_0000_0000;
+pub const KERNEL_SPACE_SIZE: u64 = 0x_0100_0000_0000; // 1 TiB
+// frames mapped at more than one address at once (see 'share_page'), fixed so the manager never allocates
+const MAX_SHARED_FRAMES: usize = 256;
+
+// using OnceCell, so accessing the manager never allocates
+static VMM: OnceCell<Mutex<VirtualMemoryManager>> = OnceCell::uninit();
+// CPU holding VMM, so a fault on the CPU that's inside the manager fails instead of deadlocking
+static VMM_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+const NO_OWNER: usize = usize::MAX;
+
+// single owner of the active page table and of all physical frames
+// every mapping in the kernel should be created through it, so frames are accounted for and can be given back
+// it never allocates on the heap, because heap growth itself goes through here (that would deadlock)
+pub struct VirtualMemoryManager {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: BitmapFrameAllocator,
+    physical_memory_offset: VirtAddr,
+    next_free: u64, // bump pointer inside kernel space, address space is plentiful so it's never reused
+    shared: [Option<SharedFrame>; MAX_SHARED_FRAMES],
+}
+
+// frame with more than one mapping, it's freed only once the last of them is gone
+#[derive(Debug, Clone, Copy)]
+struct SharedFrame {
+    frame: PhysFrame<Size4KiB>,
+    mappings: usize,
 }
 
-impl BootInfoFrameAllocator {
-    // caller needs to guuarantee that passed memory map is valid
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0
+impl VirtualMemoryManager {
+    // maps 'size' bytes starting at 'start' to freshly allocated frames
+    // on failure pages mapped so far are rolled back, so the region is either fully mapped or not at all
+    pub fn map_region(&mut self, start: VirtAddr, size: usize, flags: PageTableFlags)
+        -> Result<(), MapToError<Size4KiB>>
+    {
+        for (i, page) in page_range(start, size).enumerate() {
+            let result = match self.frame_allocator.allocate_frame() {
+                Some(frame) => unsafe {
+                    self.mapper
+                        .map_to(page, frame, flags, &mut self.frame_allocator)
+                        .map(|flush| flush.flush())
+                        .inspect_err(|_| self.frame_allocator.deallocate_frame(frame))
+                },
+                None => Err(MapToError::FrameAllocationFailed),
+            };
+
+            if let Err(err) = result {
+                let mapped = i * PAGE_SIZE;
+                if mapped > 0 {
+                    self.unmap_region(start, mapped).expect("rolling back partial mapping failed");
+                }
+                return Err(err);
+            }
         }
+
+        Ok(())
     }
 
-    // returns iterator over usable frames specified in memory map
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        // get usable regions
-        let usable_regions = regions
-            .filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions
-            .map(|r| r.range.start_addr()..r.range.end_addr());
-        // transfor to an iterator of frame start addresses
-        let frame_addresses = addr_ranges // flat_map removes internal Itertator from step_by
-            .flat_map(|r| r.step_by(4096)); // step through region address ranges by 4KiB
-        // create PhysFrame types from start addresses
-        frame_addresses
-            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    // maps already existing physical memory (MMIO, firmware tables), frames are not owned by the manager
+    pub fn map_physical_region(&mut self, start: VirtAddr, phys: PhysAddr, size: usize, flags: PageTableFlags)
+        -> Result<(), MapToError<Size4KiB>>
+    {
+        let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+        for (i, page) in page_range(start, size).enumerate() {
+            let frame = first_frame + i as u64;
+            unsafe {
+                self.mapper.map_to(page, frame, flags, &mut self.frame_allocator)?.flush();
+            }
+        }
+
+        Ok(())
+    }
+
+    // maps device registers into fresh kernel address space with caching disabled, returns address matching 'phys'
+    pub fn map_mmio(&mut self, phys: PhysAddr, size: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        let offset = phys.as_u64() % PAGE_SIZE as u64;
+        let size = size + offset as usize;
+        let start = self.reserve(size).ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE
+            | PageTableFlags::WRITE_THROUGH | PageTableFlags::NO_EXECUTE;
+        self.map_physical_region(start, phys.align_down(PAGE_SIZE as u64), size, flags)?;
+        Ok(start + offset)
+    }
+
+    // fresh frame below 'limit' mapped at the virtual address equal to its physical one, for code that keeps
+    // running at the same address while paging gets enabled (application processor startup)
+    pub fn allocate_identity_page(&mut self, limit: PhysAddr) -> Result<PhysAddr, MapToError<Size4KiB>> {
+        let frame = self.frame_allocator.allocate_frame_below(limit).ok_or(MapToError::FrameAllocationFailed)?;
+        let page = Page::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let result = unsafe { self.mapper.map_to(page, frame, flags, &mut self.frame_allocator) };
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::PageAlreadyMapped(existing)) if existing == frame => {} // bootloader identity maps some of low memory
+            Err(err) => {
+                unsafe { self.frame_allocator.deallocate_frame(frame) };
+                return Err(err);
+            }
+        }
+        Ok(frame.start_address())
+    }
+
+    // maps a single fresh frame at 'page' and fills it with zeros (through physical memory mapping, flags dont have to allow writes)
+    // frame is zeroed before it's mapped, another CPU touching the page never sees old data or has its write wiped
+    pub fn map_zeroed_page(&mut self, page: VirtAddr, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        let page = Page::<Size4KiB>::containing_address(page);
+        let frame = self.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        let frame_ptr: *mut u8 = self.phys_to_virt(frame.start_address()).as_mut_ptr();
+        unsafe {
+            frame_ptr.write_bytes(0, PAGE_SIZE);
+            self.mapper
+                .map_to(page, frame, flags, &mut self.frame_allocator)
+                .map(|flush| flush.flush())
+                .inspect_err(|_| self.frame_allocator.deallocate_frame(frame))
+        }
+    }
+
+    // maps the frame behind 'page' at 'to' as well, both mappings lose WRITABLE so writes can be split off
+    // with 'copy_page' (from a CopyOnWrite region), 'flags' are used for the new mapping
+    pub fn share_page(&mut self, page: VirtAddr, to: VirtAddr, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        let page = Page::<Size4KiB>::containing_address(page);
+        let to = Page::<Size4KiB>::containing_address(to);
+        let frame = self.mapper.translate_page(page).expect("share_page called on unmapped page");
+        let slot = match self.shared.iter().position(|s| s.is_some_and(|s| s.frame == frame)) {
+            Some(slot) => slot,
+            None => self.shared.iter().position(Option::is_none).ok_or(MapToError::FrameAllocationFailed)?,
+        };
+
+        unsafe {
+            self.mapper.map_to(to, frame, flags - PageTableFlags::WRITABLE, &mut self.frame_allocator)?.flush();
+        }
+        let page_flags = self.flags(page.start_address()).expect("page translated above");
+        unsafe {
+            self.mapper.update_flags(page, page_flags - PageTableFlags::WRITABLE).expect("page translated above").flush();
+        }
+        let shared = self.shared[slot].get_or_insert(SharedFrame { frame, mappings: 1 });
+        shared.mappings += 1;
+        Ok(())
+    }
+
+    // gives 'page' a private copy of its frame mapped with 'flags' (copy-on-write)
+    // the last mapping of a frame keeps it and just gets 'flags', nothing is copied then
+    pub fn copy_page(&mut self, page: VirtAddr, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        let page = Page::<Size4KiB>::containing_address(page);
+        let old_frame = self.mapper.translate_page(page).expect("copy_page called on unmapped page");
+        if !self.shared.iter().flatten().any(|s| s.frame == old_frame) {
+            unsafe { self.mapper.update_flags(page, flags).expect("page translated above").flush() };
+            return Ok(());
+        }
+        let new_frame = self.frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+
+        let src: *const u8 = self.phys_to_virt(old_frame.start_address()).as_ptr();
+        let dst: *mut u8 = self.phys_to_virt(new_frame.start_address()).as_mut_ptr();
+        unsafe { dst.copy_from_nonoverlapping(src, PAGE_SIZE) };
+
+        self.mapper.unmap(page).expect("page translated above").1.ignore(); // flushed by map_to below
+        unsafe {
+            self.mapper.map_to(page, new_frame, flags, &mut self.frame_allocator)?.flush();
+        }
+        self.release_frame(old_frame);
+        Ok(())
+    }
+
+    // unmaps region created with 'map_region' and gives its frames back to the frame allocator
+    // frames still mapped somewhere else (see 'share_page') stay with those mappings
+    pub fn unmap_region(&mut self, start: VirtAddr, size: usize) -> Result<(), UnmapError> {
+        for page in page_range(start, size) {
+            let (frame, flush) = self.mapper.unmap(page)?;
+            flush.flush();
+            self.release_frame(frame);
+        }
+
+        Ok(())
+    }
+
+    // one mapping of 'frame' is gone, frees it if that was the last one
+    fn release_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        match self.shared.iter_mut().find(|s| s.is_some_and(|s| s.frame == frame)) {
+            Some(slot) => {
+                let shared = slot.as_mut().expect("slot matched above");
+                shared.mappings -= 1;
+                if shared.mappings == 1 {
+                    *slot = None; // a single mapping left, it owns the frame again
+                }
+            }
+            None => unsafe { self.frame_allocator.deallocate_frame(frame) },
+        }
+    }
+
+    // unmaps region created with 'map_physical_region', frames are left alone
+    pub fn unmap_physical_region(&mut self, start: VirtAddr, size: usize) -> Result<(), UnmapError> {
+        for page in page_range(start, size) {
+            self.mapper.unmap(page)?.1.flush();
+        }
+
+        Ok(())
+    }
+
+    // changes flags of every page in the region, e.g. to make it read-only
+    pub fn protect(&mut self, start: VirtAddr, size: usize, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+        for page in page_range(start, size) {
+            unsafe { self.mapper.update_flags(page, flags)?.flush() };
+        }
+
+        Ok(())
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapper.translate_addr(addr)
+    }
+
+    // flags of the page 'addr' is in, None if it isn't mapped
+    pub fn flags(&self, addr: VirtAddr) -> Option<PageTableFlags> {
+        match self.mapper.translate(addr) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    }
+
+    // reserves 'size' bytes (rounded up to pages) of kernel address space, nothing gets mapped
+    pub fn reserve(&mut self, size: usize) -> Option<VirtAddr> {
+        let size = align_up(size as u64, PAGE_SIZE as u64);
+        let start = self.next_free;
+        if start + size > KERNEL_SPACE_START + KERNEL_SPACE_SIZE {
+            return None;
+        }
+        self.next_free += size;
+        Some(VirtAddr::new(start))
     }
-}
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+    // reserves address space and maps fresh frames into it
+    pub fn allocate_region(&mut self, size: usize, flags: PageTableFlags) -> Result<VirtAddr, MapToError<Size4KiB>> {
+        let start = self.reserve(size).ok_or(MapToError::FrameAllocationFailed)?;
+        self.map_region(start, size, flags)?;
+        Ok(start)
     }
+
+    pub fn phys_to_virt(&self, phys: PhysAddr) -> VirtAddr {
+        self.physical_memory_offset + phys.as_u64()
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.frame_allocator.free_frames()
+    }
+}
+
+// runs closure with the manager locked, interrupts are disabled so a handler can't deadlock on it
+pub fn with_vmm<R>(f: impl FnOnce(&mut VirtualMemoryManager) -> R) -> R {
+    try_with_vmm(f).expect("memory::init not called, or VMM used while this CPU holds it")
+}
+
+// like 'with_vmm', but gives up instead of deadlocking when this CPU already holds the manager (or it's not initialized yet)
+// used by fault handlers, which could have interrupted the lock holder, another CPU holding it is just waited for
+pub(crate) fn try_with_vmm<R>(f: impl FnOnce(&mut VirtualMemoryManager) -> R) -> Option<R> {
+    let vmm = VMM.try_get().ok()?;
+    interrupts::without_interrupts(|| {
+        let cpu = smp::cpu_id();
+        if VMM_OWNER.load(Ordering::Acquire) == cpu {
+            return None;
+        }
+        let mut vmm = vmm.lock();
+        VMM_OWNER.store(cpu, Ordering::Release);
+        let result = f(&mut vmm);
+        VMM_OWNER.store(NO_OWNER, Ordering::Release);
+        Some(result)
+    })
 }
 
-/// # Safety
-/// this function is unsafe because the caller must guarantee that the complete physical memory is mapped to virtual memory at the passed physical_memory_offset
-pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    unsafe {
+pub fn init(boot_info: &'static BootInfo) {
+    let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    // bootloader maps complete physical memory at this offset and gives us valid memory map
+    let (mapper, frame_allocator) = unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
-        OffsetPageTable::new(level_4_table, physical_memory_offset)
-    }
+        (
+            OffsetPageTable::new(level_4_table, physical_memory_offset),
+            BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset),
+        )
+    };
+
+    VMM.try_init_once(|| Mutex::new(VirtualMemoryManager {
+        mapper,
+        frame_allocator,
+        physical_memory_offset,
+        next_free: KERNEL_SPACE_START,
+        shared: [None; MAX_SHARED_FRAMES],
+    })).expect("memory::init should only be called once");
+}
+
+// pages overlapping 'size' bytes from 'start', none for a zero size
+fn page_range(start: VirtAddr, size: usize) -> impl Iterator<Item = Page<Size4KiB>> {
+    let start_page = Page::containing_address(start);
+    let end_page = match size {
+        0 => start_page,
+        size => Page::containing_address(start + (size as u64 - 1)) + 1,
+    };
+    Page::range(start_page, end_page)
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
 }
 
 // returns a mutable reference to the active level 4 table.
@@ -65,4 +337,4 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
 
     unsafe {&mut *page_table_ptr} // this unsafe is not mandatory, its here just to silence compiler warnings
-}
\ No newline at end of file
+}
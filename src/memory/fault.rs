@@ -0,0 +1,169 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    structures::{idt::PageFaultErrorCode, paging::{mapper::MapToError, PageTableFlags}},
+    VirtAddr,
+};
+
+use super::{try_with_vmm, PAGE_SIZE};
+use crate::smp;
+
+// fixed size, so registering and looking up regions never allocates (page fault can hit while heap is locked)
+const MAX_REGIONS: usize = 64;
+
+static REGIONS: Mutex<[Option<FaultRegion>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+// CPU holding REGIONS, a fault while this CPU looks through them can't be resolved
+static REGIONS_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+const NO_OWNER: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RegionKind {
+    // pages are mapped to zeroed frames with given flags on first access
+    Lazy(PageTableFlags),
+    // any access is a bug (e.g. stack overflow), reported as fatal with the region name
+    Guard,
+    // pages are mapped read-only, first write copies the frame and maps the copy with given flags
+    CopyOnWrite(PageTableFlags),
+    // owner resolves the fault itself
+    Custom(fn(&PageFault) -> FaultResolution),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FaultRegion {
+    pub start: VirtAddr,
+    pub size: usize,
+    pub kind: RegionKind,
+    pub name: &'static str,
+}
+
+impl FaultRegion {
+    fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr < self.start + self.size as u64
+    }
+
+    fn overlaps(&self, start: VirtAddr, size: usize) -> bool {
+        start < self.start + self.size as u64 && self.start < start + size as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    pub addr: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+    pub region: FaultRegion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultResolution {
+    Resolved, // mapping fixed, faulting instruction is retried
+    Fatal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    TableFull,
+    Overlap,
+}
+
+pub fn register_region(start: VirtAddr, size: usize, kind: RegionKind, name: &'static str) -> Result<(), RegionError> {
+    with_regions(|regions| {
+        if regions.iter().flatten().any(|r| r.overlaps(start, size)) {
+            return Err(RegionError::Overlap);
+        }
+
+        let slot = regions.iter_mut().find(|r| r.is_none()).ok_or(RegionError::TableFull)?;
+        *slot = Some(FaultRegion { start, size, kind, name });
+        Ok(())
+    })
+    .expect("regions used while this CPU holds them")
+}
+
+// removes region starting at 'start', returns it if it was registered
+pub fn unregister_region(start: VirtAddr) -> Option<FaultRegion> {
+    with_regions(|regions| {
+        regions
+            .iter_mut()
+            .find(|r| r.is_some_and(|r| r.start == start))
+            .and_then(|r| r.take())
+    })
+    .flatten()
+}
+
+pub fn find_region(addr: VirtAddr) -> Option<FaultRegion> {
+    with_regions(|regions| regions.iter().flatten().find(|r| r.contains(addr)).copied()).flatten()
+}
+
+// None when this CPU already holds the regions, i.e. the fault hit while it was looking through them
+// another CPU holding them (to register one or resolve its own fault) is just waited for
+fn with_regions<R>(f: impl FnOnce(&mut [Option<FaultRegion>; MAX_REGIONS]) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let cpu = smp::cpu_id();
+        if REGIONS_OWNER.load(Ordering::Acquire) == cpu {
+            return None;
+        }
+        let mut regions = REGIONS.lock();
+        REGIONS_OWNER.store(cpu, Ordering::Release);
+        let result = f(&mut regions);
+        REGIONS_OWNER.store(NO_OWNER, Ordering::Release);
+        Some(result)
+    })
+}
+
+// called by the page fault handler, Ok means the fault was resolved and execution can resume
+// Err carries the name of the region the address belongs to (if any), for the fatal report
+pub(crate) fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), Option<&'static str>> {
+    // lock is released before resolving, so a custom handler can (un)register regions itself
+    // None if the fault hit while this CPU was registering or looking up a region, it can't be handled then
+    let region = find_region(addr).ok_or(None)?;
+    let fault = PageFault { addr, error_code, region };
+
+    let resolution = match region.kind {
+        RegionKind::Lazy(flags) => map_lazy_page(&fault, flags),
+        RegionKind::Guard => FaultResolution::Fatal,
+        RegionKind::CopyOnWrite(flags) => copy_on_write(&fault, flags),
+        RegionKind::Custom(handler) => handler(&fault),
+    };
+
+    match resolution {
+        FaultResolution::Resolved => Ok(()),
+        FaultResolution::Fatal => Err(Some(region.name)),
+    }
+}
+
+fn map_lazy_page(fault: &PageFault, flags: PageTableFlags) -> FaultResolution {
+    if fault.error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return FaultResolution::Fatal; // page is there, access itself isn't allowed
+    }
+
+    let page = fault.addr.align_down(PAGE_SIZE as u64);
+    // another CPU touching the same page might have mapped it while we waited for the VMM
+    let mapped = try_with_vmm(|vmm| matches!(
+        vmm.map_zeroed_page(page, flags),
+        Ok(()) | Err(MapToError::PageAlreadyMapped(_))
+    ));
+    resolution(mapped)
+}
+
+fn copy_on_write(fault: &PageFault, flags: PageTableFlags) -> FaultResolution {
+    let write_to_present = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if !fault.error_code.contains(write_to_present) {
+        return FaultResolution::Fatal; // only writes to mapped pages are expected here
+    }
+
+    let page = fault.addr.align_down(PAGE_SIZE as u64);
+    let copied = try_with_vmm(|vmm| {
+        // already copied by another CPU that wrote to the page at the same time
+        vmm.flags(page).is_some_and(|flags| flags.contains(PageTableFlags::WRITABLE))
+            || vmm.copy_page(page, flags).is_ok()
+    });
+    resolution(copied)
+}
+
+// None when the fault happened inside the VMM on this CPU, in that case it can't be resolved
+fn resolution(result: Option<bool>) -> FaultResolution {
+    match result {
+        Some(true) => FaultResolution::Resolved,
+        _ => FaultResolution::Fatal,
+    }
+}
@@ -0,0 +1,293 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+// number of bitmap words covering one 2 MiB frame (512 frames / 64 bits)
+const WORDS_PER_HUGE_FRAME: usize = (Size2MiB::SIZE / FRAME_SIZE) as usize / BITS_PER_WORD;
+
+// one bit per 4 KiB physical frame, starting from physical address 0
+// set bit means the frame is used (or not usable at all), cleared bit means it's free
+// allocation scans words from a 'next' hint, so finding a free frame usually takes a single word check
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize, // number of frames covered by the bitmap
+    free_frames: usize,
+    next: usize, // word index where the next search starts
+}
+
+impl BitmapFrameAllocator {
+    /// # Safety
+    /// caller needs to guarantee that passed memory map is valid and that the complete physical memory
+    /// is mapped at 'physical_memory_offset', the bitmap itself is stored inside the first usable region big enough to hold it
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let frame_count = Self::frame_count(memory_map);
+        let bitmap_bytes = Self::words_for(frame_count) * 8;
+
+        let region = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_bytes as u64)
+            .expect("no usable region big enough for frame bitmap");
+
+        let bitmap_start = region.range.start_addr();
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let storage = unsafe { slice::from_raw_parts_mut(bitmap_ptr, bitmap_bytes / 8) };
+
+        let mut allocator = Self::with_storage(memory_map, storage);
+        // frames holding the bitmap are not free anymore
+        let bitmap_end = bitmap_start + bitmap_bytes as u64;
+        let first = (bitmap_start / FRAME_SIZE) as usize;
+        let last = bitmap_end.div_ceil(FRAME_SIZE) as usize;
+        for frame in first..last {
+            allocator.mark_used(frame);
+        }
+        allocator
+    }
+
+    // builds allocator on top of caller provided storage, which must have at least 'words_for(frame_count)' words
+    // frames are never touched, so memory map doesnt have to describe real memory (used by tests)
+    pub fn with_storage(memory_map: &MemoryMap, storage: &'static mut [u64]) -> Self {
+        let frame_count = Self::frame_count(memory_map);
+        assert!(storage.len() >= Self::words_for(frame_count), "frame bitmap storage too small");
+
+        storage.fill(u64::MAX); // everything is used until memory map says otherwise
+        let mut allocator = BitmapFrameAllocator {
+            bitmap: storage,
+            frame_count,
+            free_frames: 0,
+            next: 0,
+        };
+
+        let usable_regions = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+        for region in usable_regions {
+            let first = (region.range.start_addr() / FRAME_SIZE) as usize;
+            let last = (region.range.end_addr() / FRAME_SIZE) as usize;
+            for frame in first..last {
+                allocator.mark_free(frame);
+            }
+        }
+        allocator
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.frame_count
+    }
+
+    // lowest free frame below 'limit', for memory that has to be reachable from real mode (AP startup code)
+    // frame 0 is never handed out
+    pub fn allocate_frame_below(&mut self, limit: PhysAddr) -> Option<PhysFrame<Size4KiB>> {
+        let end = ((limit.as_u64() / FRAME_SIZE) as usize).min(self.frame_count);
+        let frame = (1..end).find(|&frame| !self.is_used(frame))?;
+        self.mark_used(frame);
+        Some(PhysFrame::containing_address(PhysAddr::new(frame as u64 * FRAME_SIZE)))
+    }
+
+    fn frame_count(memory_map: &MemoryMap) -> usize {
+        memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| (r.range.end_addr() / FRAME_SIZE) as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn words_for(frame_count: usize) -> usize {
+        frame_count.div_ceil(BITS_PER_WORD)
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    fn mark_used(&mut self, frame: usize) {
+        if !self.is_used(frame) {
+            self.bitmap[frame / BITS_PER_WORD] |= 1 << (frame % BITS_PER_WORD);
+            self.free_frames -= 1;
+        }
+    }
+
+    fn mark_free(&mut self, frame: usize) {
+        if self.is_used(frame) {
+            self.bitmap[frame / BITS_PER_WORD] &= !(1 << (frame % BITS_PER_WORD));
+            self.free_frames += 1;
+        }
+    }
+
+    fn frame_index<S: PageSize>(frame: PhysFrame<S>) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        let words = self.bitmap.len();
+        // start at the hint and wrap around once
+        for offset in 0..words {
+            let word_index = (self.next + offset) % words;
+            let word = self.bitmap[word_index];
+            if word == u64::MAX {
+                continue; // whole word used
+            }
+
+            let frame = word_index * BITS_PER_WORD + word.trailing_ones() as usize;
+            if frame >= self.frame_count {
+                continue; // padding bits past the last frame
+            }
+            self.mark_used(frame);
+            self.next = word_index;
+            return Some(PhysFrame::containing_address(PhysAddr::new(frame as u64 * FRAME_SIZE)));
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = Self::frame_index(frame);
+        assert!(self.is_used(index), "double free of frame {:?}", frame);
+        self.mark_free(index);
+        self.next = self.next.min(index / BITS_PER_WORD); // freed frames get reused first
+    }
+}
+
+// 2 MiB frame is handed out only if all 512 frames of an aligned group are free
+// this maps to 8 whole bitmap words, so no bit fiddling is needed
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let huge_frames = self.frame_count / (WORDS_PER_HUGE_FRAME * BITS_PER_WORD);
+        for huge in 0..huge_frames {
+            let words = huge * WORDS_PER_HUGE_FRAME..(huge + 1) * WORDS_PER_HUGE_FRAME;
+            if self.bitmap[words.clone()].iter().all(|&w| w == 0) {
+                self.bitmap[words].fill(u64::MAX);
+                self.free_frames -= WORDS_PER_HUGE_FRAME * BITS_PER_WORD;
+                let addr = PhysAddr::new(huge as u64 * Size2MiB::SIZE);
+                return Some(PhysFrame::containing_address(addr));
+            }
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let first = Self::frame_index(frame);
+        for index in first..first + WORDS_PER_HUGE_FRAME * BITS_PER_WORD {
+            assert!(self.is_used(index), "double free of frame {:?}", frame);
+            self.mark_free(index);
+        }
+        self.next = self.next.min(first / BITS_PER_WORD);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    // builds memory map with given usable ranges (in frame numbers), everything else is reserved
+    fn memory_map(usable: &[(u64, u64)]) -> MemoryMap {
+        let mut map = MemoryMap::new();
+        for &(start, end) in usable {
+            map.add_region(MemoryRegion {
+                range: FrameRange::new(start * FRAME_SIZE, end * FRAME_SIZE),
+                region_type: MemoryRegionType::Usable,
+            });
+        }
+        map
+    }
+
+    // each test owns its own static bitmap storage
+    fn storage<const N: usize>(words: *mut [u64; N]) -> &'static mut [u64] {
+        unsafe { &mut *words }
+    }
+
+    #[test_case]
+    fn test_counts_free_frames() {
+        static mut STORAGE: [u64; 4] = [0; 4];
+        let map = memory_map(&[(1, 10), (64, 100)]);
+        let mut allocator = BitmapFrameAllocator::with_storage(&map, storage(&raw mut STORAGE));
+        assert_eq!(allocator.free_frames(), 9 + 36);
+        assert_eq!(allocator.total_frames(), 100);
+
+        let frame: PhysFrame<Size4KiB> = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address().as_u64(), FRAME_SIZE); // frame 0 is reserved
+        assert_eq!(allocator.free_frames(), 9 + 36 - 1);
+    }
+
+    #[test_case]
+    fn test_exhaustion() {
+        static mut STORAGE: [u64; 2] = [0; 2];
+        let map = memory_map(&[(3, 8), (70, 73)]);
+        let mut allocator = BitmapFrameAllocator::with_storage(&map, storage(&raw mut STORAGE));
+
+        for _ in 0..8 {
+            let frame: Option<PhysFrame<Size4KiB>> = allocator.allocate_frame();
+            let index = frame.expect("allocator exhausted too early").start_address().as_u64() / FRAME_SIZE;
+            assert!((3..8).contains(&index) || (70..73).contains(&index));
+        }
+        let frame: Option<PhysFrame<Size4KiB>> = allocator.allocate_frame();
+        assert!(frame.is_none());
+        assert_eq!(allocator.free_frames(), 0);
+    }
+
+    #[test_case]
+    fn test_reuse() {
+        static mut STORAGE: [u64; 1] = [0; 1];
+        let map = memory_map(&[(0, 4)]);
+        let mut allocator = BitmapFrameAllocator::with_storage(&map, storage(&raw mut STORAGE));
+
+        let frames: [PhysFrame<Size4KiB>; 4] = core::array::from_fn(|_| allocator.allocate_frame().unwrap());
+        assert!(FrameAllocator::<Size4KiB>::allocate_frame(&mut allocator).is_none());
+
+        unsafe { allocator.deallocate_frame(frames[2]) };
+        assert_eq!(allocator.free_frames(), 1);
+        let frame: PhysFrame<Size4KiB> = allocator.allocate_frame().unwrap();
+        assert_eq!(frame, frames[2]);
+    }
+
+    #[test_case]
+    fn test_allocate_below() {
+        static mut STORAGE: [u64; 2] = [0; 2];
+        let map = memory_map(&[(0, 3), (90, 100)]);
+        let mut allocator = BitmapFrameAllocator::with_storage(&map, storage(&raw mut STORAGE));
+
+        let limit = PhysAddr::new(3 * FRAME_SIZE);
+        let frames = [allocator.allocate_frame_below(limit), allocator.allocate_frame_below(limit)];
+        assert_eq!(frames.map(|frame| frame.unwrap().start_address().as_u64() / FRAME_SIZE), [1, 2]);
+        assert!(allocator.allocate_frame_below(limit).is_none(), "frames above the limit were handed out");
+        assert_eq!(allocator.free_frames(), 10);
+    }
+
+    #[test_case]
+    fn test_huge_frames() {
+        static mut STORAGE: [u64; 16] = [0; 16];
+        // first 2 MiB group has a hole at frame 0, so only the second one can be handed out
+        let map = memory_map(&[(1, 1024)]);
+        let mut allocator = BitmapFrameAllocator::with_storage(&map, storage(&raw mut STORAGE));
+
+        let huge: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+        assert_eq!(huge.start_address().as_u64(), Size2MiB::SIZE);
+        assert!(FrameAllocator::<Size2MiB>::allocate_frame(&mut allocator).is_none());
+        assert_eq!(allocator.free_frames(), 511);
+
+        unsafe { allocator.deallocate_frame(huge) };
+        assert_eq!(allocator.free_frames(), 1023);
+    }
+}
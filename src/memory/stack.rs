@@ -0,0 +1,62 @@
+use x86_64::{
+    structures::paging::{mapper::{MapToError, UnmapError}, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+use super::fault::{self, RegionKind};
+use super::{with_vmm, PAGE_SIZE};
+
+// every stack lives in its own reservation:
+// | guard page (never mapped) | stack pages ... | <- top
+// stacks grow downwards, so an overflow runs into the guard page and page faults instead of overwriting whatever lies below
+#[derive(Debug)]
+pub struct KernelStack {
+    guard: VirtAddr,
+    pages: usize,
+}
+
+impl KernelStack {
+    // initial stack pointer, x86 stacks grow downwards so it's the end of the mapped range
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + (self.pages * PAGE_SIZE) as u64
+    }
+
+    // lowest usable address
+    pub fn bottom(&self) -> VirtAddr {
+        self.guard + PAGE_SIZE as u64
+    }
+
+    pub fn guard_page(&self) -> VirtAddr {
+        self.guard
+    }
+
+    pub fn size(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+
+    /// # Safety
+    /// stack can't be in use anymore (no thread running on it, not referenced by TSS)
+    pub unsafe fn free(self) -> Result<(), UnmapError> {
+        fault::unregister_region(self.guard);
+        with_vmm(|vmm| vmm.unmap_region(self.bottom(), self.size()))
+    }
+}
+
+// reserves 'pages' + 1 pages of address space, leaves the lowest one unmapped as a guard and maps the rest
+pub fn allocate_stack(pages: usize) -> Result<KernelStack, MapToError<Size4KiB>> {
+    assert!(pages > 0, "stack needs at least one page");
+
+    let stack = with_vmm(|vmm| -> Result<_, MapToError<Size4KiB>> {
+        let guard = vmm
+            .reserve((pages + 1) * PAGE_SIZE)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        vmm.map_region(guard + PAGE_SIZE as u64, pages * PAGE_SIZE, flags)?;
+        Ok(KernelStack { guard, pages })
+    })?;
+
+    // address space is never reused, so the guard page can't overlap anything
+    fault::register_region(stack.guard, PAGE_SIZE, RegionKind::Guard, "kernel stack guard page")
+        .expect("failed to register stack guard page");
+    Ok(stack)
+}
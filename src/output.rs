@@ -0,0 +1,240 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+use crate::smp::{self, MAX_CPUS};
+use crate::{serial, thread, vga_buffer};
+
+const BUFFER_SIZE: usize = 4096; // bytes per CPU and target
+const CHUNK_SIZE: usize = 128; // bytes handed to a writer at once
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+const PANIC_PATIENCE: usize = 10_000_000; // spins a panicking CPU gives another CPU to release a writer
+const FLUSH_PATIENCE: usize = 100_000_000; // spins 'flush_blocking' gives a drain in progress, enough for a preempted flusher to run again
+const NO_OWNER: usize = usize::MAX;
+
+// 'print!' and 'serial_print!' only copy into the buffer of the CPU they run on, no lock is taken
+// only one CPU at a time drains the buffers into the writers, so a CPU stuck printing can't block the others
+static BUFFERS: [[CpuBuffer; Target::COUNT]; MAX_CPUS] = [const { [const { CpuBuffer::new() }; Target::COUNT] }; MAX_CPUS];
+// CPU draining the buffers
+static FLUSH_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+// until the flusher thread runs, whoever prints drains the buffers right away
+static FLUSHER_RUNNING: AtomicBool = AtomicBool::new(false);
+// set by 'panic_mode', buffers are skipped from then on
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Vga,
+    Serial, // log port, see 'serial::set_log_port'
+}
+
+impl Target {
+    const COUNT: usize = 2;
+
+    // false when the writer is busy on this CPU
+    fn write_bytes(self, bytes: &[u8]) -> bool {
+        match self {
+            Target::Vga => vga_buffer::write_bytes(bytes),
+            Target::Serial => serial::write_bytes(serial::log_port(), bytes).is_ok(),
+        }
+    }
+
+    fn write_fmt(self, args: fmt::Arguments) -> bool {
+        match self {
+            Target::Vga => vga_buffer::try_print(args),
+            Target::Serial => serial::write_to(serial::log_port(), args).is_ok(),
+        }
+    }
+}
+
+// ring written only by its CPU and read only by the CPU owning FLUSH_OWNER
+struct CpuBuffer {
+    bytes: [AtomicU8; BUFFER_SIZE],
+    head: AtomicUsize, // bytes ever written
+    tail: AtomicUsize, // bytes ever drained
+    writing: AtomicBool, // set while the CPU formats into it, a print nested in that (e.g. from an exception) can't use it
+    dropped: AtomicUsize, // bytes that didn't fit, reported by the next drain
+}
+
+impl CpuBuffer {
+    const fn new() -> CpuBuffer {
+        CpuBuffer {
+            bytes: [const { AtomicU8::new(0) }; BUFFER_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            writing: AtomicBool::new(false),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    // false if this CPU is already writing to the buffer
+    fn write(&self, args: fmt::Arguments) -> bool {
+        if self.writing.swap(true, Ordering::Acquire) {
+            return false;
+        }
+        let mut record = Record { buffer: self, head: self.head.load(Ordering::Relaxed) };
+        let _ = record.write_fmt(args); // a failing Display impl only cuts its own output short
+        self.head.store(record.head, Ordering::Release);
+        self.writing.store(false, Ordering::Release);
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire) && self.dropped.load(Ordering::Relaxed) == 0
+    }
+
+    // false when the writer was busy, what's left stays in the buffer
+    fn drain(&self, target: Target) -> bool {
+        let head = self.head.load(Ordering::Acquire);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        let mut chunk = [0; CHUNK_SIZE];
+        while tail != head {
+            let len = (head - tail).min(CHUNK_SIZE);
+            for (i, byte) in chunk[..len].iter_mut().enumerate() {
+                *byte = self.bytes[(tail + i) % BUFFER_SIZE].load(Ordering::Relaxed);
+            }
+            if !target.write_bytes(&chunk[..len]) {
+                return false; // this CPU was interrupted while using the writer
+            }
+            tail += len;
+            self.tail.store(tail, Ordering::Release);
+        }
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            target.write_fmt(format_args!("[{} bytes of output dropped]\n", dropped));
+        }
+        true
+    }
+}
+
+// bytes of one print, published together once it's formatted
+struct Record<'a> {
+    buffer: &'a CpuBuffer,
+    head: usize,
+}
+
+impl Write for Record<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.head - self.buffer.tail.load(Ordering::Acquire) == BUFFER_SIZE {
+                self.buffer.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.buffer.bytes[self.head % BUFFER_SIZE].store(byte, Ordering::Relaxed);
+            self.head += 1;
+        }
+        Ok(())
+    }
+}
+
+// what the print macros expand to, never blocks on a writer
+pub fn write(target: Target, args: fmt::Arguments) {
+    if PANICKING.load(Ordering::Acquire) {
+        target.write_fmt(args);
+        return;
+    }
+    let buffered = interrupts::without_interrupts(|| BUFFERS[smp::cpu_id()][target as usize].write(args));
+    if !buffered {
+        target.write_fmt(args); // printing while formatting another print, goes around the buffer
+    }
+    if !FLUSHER_RUNNING.load(Ordering::Acquire) {
+        flush();
+    }
+}
+
+// drains every CPU's buffers, returns right away if another CPU (or an interrupted drain on this one) is at it
+pub fn flush() {
+    if take_flush_owner() {
+        drain_and_release();
+    }
+}
+
+// like 'flush', but waits for a drain in progress instead of leaving the output to it, for when everything printed
+// so far has to be out before going on (shutdown, clearing the screen)
+// a drain that doesn't finish within FLUSH_PATIENCE spins is drained alongside, its last chunk might show up twice
+// after 'panic_mode' the buffers are drained right away, FLUSH_OWNER stays with the panicking CPU
+pub fn flush_blocking() {
+    let mut patience = FLUSH_PATIENCE;
+    while !take_flush_owner() {
+        if PANICKING.load(Ordering::Acquire) {
+            drain_all();
+            return;
+        }
+        if patience == 0 {
+            FLUSH_OWNER.store(smp::cpu_id(), Ordering::Release);
+            break;
+        }
+        patience -= 1;
+        core::hint::spin_loop();
+    }
+    drain_and_release();
+}
+
+fn take_flush_owner() -> bool {
+    FLUSH_OWNER.compare_exchange(NO_OWNER, smp::cpu_id(), Ordering::Acquire, Ordering::Relaxed).is_ok()
+}
+
+fn drain_and_release() {
+    loop {
+        let drained = drain_all();
+        FLUSH_OWNER.store(NO_OWNER, Ordering::Release);
+        // a CPU that printed while we held FLUSH_OWNER left its output for us
+        if !drained || all_empty() || !take_flush_owner() {
+            break;
+        }
+    }
+}
+
+// drains the buffers every FLUSH_INTERVAL from a kernel thread, printing is just a copy into a buffer afterwards
+// needs thread::init on the calling CPU, the thread stays on it
+pub fn start_flusher() {
+    if !FLUSHER_RUNNING.swap(true, Ordering::AcqRel) {
+        thread::spawn(run_flusher);
+    }
+}
+
+fn run_flusher() {
+    loop {
+        flush();
+        thread::sleep(FLUSH_INTERVAL);
+    }
+}
+
+// for panic handlers and fatal exceptions: writes out what every CPU buffered and stops buffering
+// writers held by this CPU (or held by another one for too long) are unlocked by force, so the panic message gets through
+pub fn panic_mode() {
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let cpu = smp::cpu_id();
+        let mut patience = PANIC_PATIENCE;
+        while FLUSH_OWNER.compare_exchange(NO_OWNER, cpu, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            if FLUSH_OWNER.load(Ordering::Relaxed) == cpu || patience == 0 {
+                FLUSH_OWNER.store(cpu, Ordering::Release);
+                break;
+            }
+            patience -= 1;
+            core::hint::spin_loop();
+        }
+        vga_buffer::force_unlock(PANIC_PATIENCE);
+        serial::force_unlock(serial::log_port(), PANIC_PATIENCE);
+        drain_all(); // FLUSH_OWNER is kept, nothing else drains after this
+    });
+}
+
+// false if some output couldn't be written yet
+fn drain_all() -> bool {
+    let mut drained = true;
+    for buffers in &BUFFERS {
+        for (buffer, target) in buffers.iter().zip([Target::Vga, Target::Serial]) {
+            drained &= buffer.drain(target);
+        }
+    }
+    drained
+}
+
+fn all_empty() -> bool {
+    BUFFERS.iter().flatten().all(CpuBuffer::is_empty)
+}
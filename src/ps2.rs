@@ -0,0 +1,189 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::task::sync::{Mutex as AsyncMutex, Notify};
+use crate::task::timer;
+
+// 8042 PS/2 controller, keyboard is its first port, mouse the second (auxiliary) one
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64; // read
+const COMMAND: u16 = 0x64; // write
+
+const OUTPUT_FULL: u8 = 1 << 0; // data waits to be read from DATA
+const INPUT_FULL: u8 = 1 << 1; // controller hasn't taken the last byte written yet
+const AUX_DATA: u8 = 1 << 5; // byte in DATA came from the auxiliary port
+
+// controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const ENABLE_AUX: u8 = 0xa8;
+const WRITE_AUX: u8 = 0xd4; // next data byte goes to the auxiliary device
+
+const CONFIG_AUX_IRQ: u8 = 1 << 1; // IRQ12 on auxiliary data
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+const TIMEOUT_SPINS: u32 = 100_000;
+const REPLY_TIMEOUT: Duration = Duration::from_millis(20); // keyboards answer within a few ms
+const RESEND_ATTEMPTS: usize = 3;
+
+// keyboard commands and replies
+const SET_LEDS: u8 = 0xed;
+pub const ACK: u8 = 0xfa;
+pub const RESEND: u8 = 0xfe;
+pub const ECHO: u8 = 0xee;
+
+const NO_REPLY: u8 = 0x00; // key detection error on the keyboard's side, never a reply to a command
+
+static CONTROLLER: Mutex<Controller> = Mutex::new(Controller::new());
+// last reply the keyboard IRQ handler got, taken by whoever waits for one
+static KEYBOARD_REPLY: AtomicU8 = AtomicU8::new(NO_REPLY);
+// signalled by the keyboard IRQ handler with every reply
+static KEYBOARD_REPLIED: Notify = Notify::new();
+// one keyboard command at a time, held while waiting for replies
+static KEYBOARD_COMMAND: AsyncMutex<()> = AsyncMutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout, // controller didn't take or deliver a byte in time
+    NoAck(u8), // device answered a command with something else
+}
+
+struct Controller {
+    data: Port<u8>,
+    status: Port<u8>,
+    command: Port<u8>,
+}
+
+impl Controller {
+    const fn new() -> Controller {
+        Controller { data: Port::new(DATA), status: Port::new(STATUS), command: Port::new(COMMAND) }
+    }
+
+    fn wait_until(&mut self, ready: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT_SPINS {
+            if ready(unsafe { self.status.read() }) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn write_data(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_until(|status| status & INPUT_FULL == 0)?;
+        unsafe { self.data.write(byte) };
+        Ok(())
+    }
+
+    fn write_command(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_until(|status| status & INPUT_FULL == 0)?;
+        unsafe { self.command.write(byte) };
+        Ok(())
+    }
+
+    fn read_data(&mut self) -> Result<u8, Ps2Error> {
+        self.wait_until(|status| status & OUTPUT_FULL != 0)?;
+        Ok(unsafe { self.data.read() })
+    }
+
+    // skips keyboard bytes arriving in between, they're lost
+    fn read_aux(&mut self) -> Result<u8, Ps2Error> {
+        loop {
+            self.wait_until(|status| status & OUTPUT_FULL != 0)?;
+            let from_aux = unsafe { self.status.read() } & AUX_DATA != 0;
+            let byte = unsafe { self.data.read() };
+            if from_aux {
+                return Ok(byte);
+            }
+        }
+    }
+}
+
+fn with_controller<R>(f: impl FnOnce(&mut Controller) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut CONTROLLER.lock()))
+}
+
+// byte from the data port, only meant for IRQ handlers (the controller raised the IRQ because data is there)
+pub fn read_irq_data() -> u8 {
+    unsafe { Port::<u8>::new(DATA).read() }
+}
+
+// bit 0 scroll lock, bit 1 num lock, bit 2 caps lock
+// keyboard acknowledges both bytes through IRQ1, so this needs the keyboard IRQ handler registered
+// replies are awaited, the executor (and its timers) keeps running other tasks meanwhile
+pub async fn set_keyboard_leds(leds: u8) -> Result<(), Ps2Error> {
+    let _command = KEYBOARD_COMMAND.lock().await;
+    keyboard_write(SET_LEDS).await?;
+    keyboard_write(leds & 0b111).await
+}
+
+// replies to commands, keyboard sends them on the same port as scancodes
+pub fn is_keyboard_reply(byte: u8) -> bool {
+    matches!(byte, ACK | RESEND | ECHO)
+}
+
+// called by the keyboard IRQ handler with the bytes 'is_keyboard_reply' accepts
+pub fn keyboard_reply(byte: u8) {
+    KEYBOARD_REPLY.store(byte, Ordering::Release);
+    KEYBOARD_REPLIED.notify_one();
+}
+
+// sends a byte to the keyboard and waits for its ACK, the byte is sent again when the keyboard asks for it
+async fn keyboard_write(byte: u8) -> Result<(), Ps2Error> {
+    let mut reply = RESEND;
+    for _ in 0..RESEND_ATTEMPTS {
+        KEYBOARD_REPLY.store(NO_REPLY, Ordering::Release);
+        with_controller(|controller| controller.write_data(byte))?;
+        reply = wait_for_keyboard_reply().await?;
+        if reply != RESEND {
+            break;
+        }
+    }
+    match reply {
+        ACK => Ok(()),
+        reply => Err(Ps2Error::NoAck(reply)),
+    }
+}
+
+async fn wait_for_keyboard_reply() -> Result<u8, Ps2Error> {
+    let reply = async {
+        loop {
+            match KEYBOARD_REPLY.swap(NO_REPLY, Ordering::Acquire) {
+                NO_REPLY => KEYBOARD_REPLIED.notified().await, // a permit left from an earlier reply just loops once more
+                reply => return reply,
+            }
+        }
+    };
+    timer::timeout(reply, REPLY_TIMEOUT).await.map_err(|_| Ps2Error::Timeout)
+}
+
+// turns on the auxiliary port and its IRQ12
+// replies are polled, so the caller should keep IRQ12 from being handled until the device is set up
+pub fn enable_aux() -> Result<(), Ps2Error> {
+    with_controller(|controller| {
+        controller.write_command(ENABLE_AUX)?;
+        controller.write_command(READ_CONFIG)?;
+        let config = controller.read_data()?;
+        controller.write_command(WRITE_CONFIG)?;
+        controller.write_data((config | CONFIG_AUX_IRQ) & !CONFIG_AUX_CLOCK_DISABLED)
+    })
+}
+
+// sends a command byte to the auxiliary device and waits for its acknowledgement
+pub fn aux_command(byte: u8) -> Result<(), Ps2Error> {
+    with_controller(|controller| {
+        controller.write_command(WRITE_AUX)?;
+        controller.write_data(byte)?;
+        match controller.read_aux()? {
+            ACK => Ok(()),
+            reply => Err(Ps2Error::NoAck(reply)),
+        }
+    })
+}
+
+// polled read of a byte the auxiliary device sends after acknowledging a command
+pub fn read_aux() -> Result<u8, Ps2Error> {
+    with_controller(|controller| controller.read_aux())
+}
@@ -1,12 +1,80 @@
-use uart_16550::SerialPort;
+use alloc::string::String;
 use spin::Mutex;
-use lazy_static::lazy_static;
-use core::fmt::Write;
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::{pin::Pin, task::{Poll, Context}};
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
 use x86_64::instructions::interrupts;
 
-const SERIAL1_PORT: u16 = 0x3F8;
+use crate::interrupts::register_irq;
+use crate::output::{self, Target};
+use crate::smp;
 
-// macros similar to VGA buffer ones
+pub mod line;
+pub mod uart;
+
+pub use line::LineDiscipline;
+pub use uart::{DataBits, FifoThreshold, Parity, StopBits, Uart, UartConfig, UartError};
+
+const PORT_COUNT: usize = 4;
+const RECEIVE_QUEUE_CAPACITY: usize = 256;
+
+// standard PC serial ports, odd ones share IRQ4, even ones IRQ3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; PORT_COUNT] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+            ComPort::Com3 => 0x3e8,
+            ComPort::Com4 => 0x2e8,
+        }
+    }
+
+    pub fn irq(self) -> u8 {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => 4,
+            ComPort::Com2 | ComPort::Com4 => 3,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+enum PortState {
+    Unprobed, // probed (and set up with the default config) on first use
+    Absent,
+    Present(Uart),
+}
+
+static PORTS: [Mutex<PortState>; PORT_COUNT] = [const { Mutex::new(PortState::Unprobed) }; PORT_COUNT];
+// set once a port is initialized, lets interrupt handlers skip absent ports without locking
+static PRESENT: [AtomicBool; PORT_COUNT] = [const { AtomicBool::new(false) }; PORT_COUNT];
+// received bytes, filled by the IRQ handlers once a 'SerialStream' for the port exists
+static RECEIVE_QUEUES: [OnceCell<ArrayQueue<u8>>; PORT_COUNT] = [const { OnceCell::uninit() }; PORT_COUNT];
+static WAKERS: [AtomicWaker; PORT_COUNT] = [const { AtomicWaker::new() }; PORT_COUNT];
+// which port 'serial_print!' and the serial console use, indices into ComPort::ALL
+static LOG_PORT: AtomicU8 = AtomicU8::new(ComPort::Com1 as u8);
+static CONSOLE_PORT: AtomicU8 = AtomicU8::new(ComPort::Com1 as u8);
+// CPU holding the port lock, so a CPU interrupted while writing doesn't deadlock on its own port
+static PORT_OWNERS: [AtomicUsize; PORT_COUNT] = [const { AtomicUsize::new(NO_OWNER) }; PORT_COUNT];
+const NO_OWNER: usize = usize::MAX;
+
+// macros similar to VGA buffer ones, output goes to the log port
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => ($crate::serial::_print(format_args!($($arg)*)));
@@ -21,20 +89,221 @@ macro_rules! serial_println {
 }
 
 #[doc(hidden)]
-pub fn _print(args: ::core::fmt::Arguments) {
+pub fn _print(args: fmt::Arguments) {
+    output::write(Target::Serial, args); // buffered, see 'output', a log port without a UART just drops it
+}
+
+// runs 'f' on the port's UART
+fn with_port<R>(port: ComPort, f: impl FnOnce(&mut Uart) -> R) -> Result<R, UartError> {
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
-}
-
-// like in VGA text buffer, lazy_static and spinlock is used
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(SERIAL1_PORT) }; // 0x3F8 is a standard port number for first serial iface
-        serial_port.init(); // lazy_static ensures this init is called only once
-        Mutex::new(serial_port)
-    };
-}
\ No newline at end of file
+        let cpu = smp::cpu_id();
+        let owner = &PORT_OWNERS[port.index()];
+        if owner.load(Ordering::Acquire) == cpu {
+            return Err(UartError::Busy);
+        }
+        let mut state = PORTS[port.index()].lock();
+        owner.store(cpu, Ordering::Release);
+        let result = with_state(port, &mut state, f);
+        owner.store(NO_OWNER, Ordering::Release);
+        result
+    })
+}
+
+// probes and initializes the port on first use
+fn with_state<R>(port: ComPort, state: &mut PortState, f: impl FnOnce(&mut Uart) -> R) -> Result<R, UartError> {
+    if let PortState::Unprobed = *state {
+        *state = match unsafe { Uart::probe(port.base()) } {
+            true => PortState::Present(unsafe { Uart::new(port.base(), UartConfig::default()) }?),
+            false => PortState::Absent,
+        };
+        PRESENT[port.index()].store(matches!(*state, PortState::Present(_)), Ordering::Release);
+    }
+    match state {
+        PortState::Present(uart) => Ok(f(uart)),
+        _ => Err(UartError::NotPresent),
+    }
+}
+
+// probes all four ports and starts handling receive interrupts of the ones that exist
+pub fn init() {
+    for port in ComPort::ALL {
+        let _ = with_port(port, |_| ());
+    }
+    for irq in [4, 3] {
+        if ComPort::ALL.into_iter().any(|port| port.irq() == irq && is_present(port)) {
+            let handler = if irq == 4 { com1_com3_interrupt } else { com2_com4_interrupt };
+            register_irq(irq, handler).expect("serial IRQ already taken");
+        }
+    }
+}
+
+pub fn is_present(port: ComPort) -> bool {
+    with_port(port, |_| ()).is_ok()
+}
+
+pub fn configure(port: ComPort, config: UartConfig) -> Result<(), UartError> {
+    with_port(port, |uart| uart.configure(config))?
+}
+
+pub fn config(port: ComPort) -> Result<UartConfig, UartError> {
+    with_port(port, |uart| uart.config())
+}
+
+// writes right away, bypassing the buffer of 'serial_print!'
+pub fn write_to(port: ComPort, args: fmt::Arguments) -> Result<(), UartError> {
+    with_port(port, |uart| {
+        let _ = uart.write_fmt(args); // a failing Display impl only cuts its own output short
+    })
+}
+
+pub(crate) fn write_bytes(port: ComPort, bytes: &[u8]) -> Result<(), UartError> {
+    with_port(port, |uart| bytes.iter().for_each(|&byte| uart.send(byte)))
+}
+
+// panic path, takes the port away from this CPU if the panic interrupted it while writing,
+// or from another CPU that doesn't release it within 'patience' spins
+pub(crate) fn force_unlock(port: ComPort, patience: usize) {
+    let owner = &PORT_OWNERS[port.index()];
+    let held_here = owner.load(Ordering::Acquire) == smp::cpu_id();
+    if held_here || !(0..patience).any(|_| PORTS[port.index()].try_lock().is_some()) {
+        unsafe { PORTS[port.index()].force_unlock() };
+        owner.store(NO_OWNER, Ordering::Release);
+    }
+}
+
+// port carrying 'serial_print!' output (kernel logs, test results)
+pub fn log_port() -> ComPort {
+    ComPort::ALL[LOG_PORT.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_log_port(port: ComPort) -> Result<(), UartError> {
+    with_port(port, |_| LOG_PORT.store(port as u8, Ordering::Relaxed))
+}
+
+// port 'SerialConsole::new' reads from and echoes to
+pub fn console_port() -> ComPort {
+    ComPort::ALL[CONSOLE_PORT.load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_console_port(port: ComPort) -> Result<(), UartError> {
+    with_port(port, |_| CONSOLE_PORT.store(port as u8, Ordering::Relaxed))
+}
+
+// IRQ4 and IRQ3 handlers, read everything the FIFOs of both ports on the line hold
+fn com1_com3_interrupt() {
+    receive(ComPort::Com1);
+    receive(ComPort::Com3);
+}
+
+fn com2_com4_interrupt() {
+    receive(ComPort::Com2);
+    receive(ComPort::Com4);
+}
+
+// registers are read directly, so a CPU printing with the port locked doesn't hold up input
+fn receive(port: ComPort) {
+    if !PRESENT[port.index()].load(Ordering::Acquire) {
+        return;
+    }
+    while let Some(byte) = unsafe { uart::receive_at(port.base()) } {
+        if let Ok(queue) = RECEIVE_QUEUES[port.index()].try_get() { // nobody reads the port yet, byte is dropped
+            if queue.push(byte).is_err() {
+                log::warn!("serial receive queue of {:?} full; dropping input", port);
+            } else {
+                WAKERS[port.index()].wake();
+            }
+        }
+    }
+}
+
+// bytes received on a port
+pub struct SerialStream {
+    port: ComPort,
+}
+
+impl SerialStream {
+    pub fn new(port: ComPort) -> Self {
+        RECEIVE_QUEUES[port.index()].try_init_once(|| ArrayQueue::new(RECEIVE_QUEUE_CAPACITY))
+            .expect("SerialStream::new should only be called once per port");
+        SerialStream { port }
+    }
+
+    pub fn port(&self) -> ComPort {
+        self.port
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let index = self.port.index();
+        let queue = RECEIVE_QUEUES[index]
+            .try_get()
+            .expect("not initialized");
+
+        // fast path
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        WAKERS[index].register(cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                WAKERS[index].take(); // remove registered waker, notification no longer needed
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+// line based terminal on a serial port: input is echoed and edited locally, programs only see whole lines
+// output written through it goes to the same port, independent of where logs go
+pub struct SerialConsole {
+    input: SerialStream,
+    line: LineDiscipline,
+}
+
+impl SerialConsole {
+    // on the console port
+    pub fn new() -> Self {
+        SerialConsole::on(console_port())
+    }
+
+    pub fn on(port: ComPort) -> Self {
+        SerialConsole { input: SerialStream::new(port), line: LineDiscipline::new() }
+    }
+
+    pub fn set_echo(&mut self, echo: bool) {
+        self.line.set_echo(echo);
+    }
+
+    // next line without its terminator
+    pub async fn read_line(&mut self) -> Option<String> {
+        let mut echo = String::new();
+        while let Some(byte) = self.input.next().await {
+            let line = self.line.push(byte, &mut echo);
+            if !echo.is_empty() {
+                let _ = self.write_str(&echo);
+                echo.clear();
+            }
+            if line.is_some() {
+                return line;
+            }
+        }
+        None
+    }
+}
+
+impl Default for SerialConsole {
+    fn default() -> Self {
+        SerialConsole::new()
+    }
+}
+
+impl Write for SerialConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_to(self.input.port(), format_args!("{}", s)).map_err(|_| fmt::Error)
+    }
+}
@@ -0,0 +1,123 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f; // what most terminals send for the backspace key
+const KILL_LINE: u8 = 0x15; // ctrl-u
+const BELL: &str = "\x07";
+
+pub const MAX_LINE: usize = 1024; // bytes, more would let the other end fill up the heap
+
+// cooked mode for a serial terminal: collects bytes into lines and produces what has to be echoed back
+// a line ends with CR, LF or CR LF, backspace removes the last character (not byte) of the unfinished line
+// characters past MAX_LINE are dropped whole, the terminal's bell rings instead of echoing them
+pub struct LineDiscipline {
+    line: Vec<u8>,
+    echo: bool,
+    after_cr: bool, // LF right after CR belongs to the same line end
+    dropping: bool, // last character didn't fit, its continuation bytes go too
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        LineDiscipline { line: Vec::new(), echo: true, after_cr: false, dropping: false }
+    }
+
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    // unfinished line so far
+    pub fn pending(&self) -> &[u8] {
+        &self.line
+    }
+
+    // handles one received byte, echo goes to 'output', returns the line once it's complete
+    // invalid UTF-8 is replaced, so a line is always a String
+    pub fn push(&mut self, byte: u8, output: &mut impl Write) -> Option<String> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        let dropping = core::mem::take(&mut self.dropping);
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                self.echo(output, "\r\n");
+                let line = core::mem::take(&mut self.line);
+                Some(String::from_utf8_lossy(&line).into_owned())
+            }
+            BACKSPACE | DELETE => {
+                if self.erase_char() {
+                    self.echo(output, "\x08 \x08");
+                }
+                None
+            }
+            KILL_LINE => {
+                while self.erase_char() {
+                    self.echo(output, "\x08 \x08");
+                }
+                None
+            }
+            byte if byte.is_ascii_control() => None, // escape sequences and the like aren't supported
+            _ if dropping && is_continuation(byte) => {
+                self.dropping = true;
+                None
+            }
+            // a multi-byte character has to fit completely, it's not split at the limit
+            _ if self.line.len() + char_len(byte) > MAX_LINE => {
+                self.dropping = true;
+                self.echo(output, BELL);
+                None
+            }
+            byte => {
+                self.line.push(byte);
+                if self.echo {
+                    // multi-byte characters are echoed once they're complete
+                    let start = self.line.iter().rposition(|&byte| !is_continuation(byte)).unwrap_or(0);
+                    if let Ok(character) = core::str::from_utf8(&self.line[start..]) {
+                        let _ = output.write_str(character);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    // removes the last character with all its UTF-8 bytes, false when the line is empty
+    fn erase_char(&mut self) -> bool {
+        match self.line.iter().rposition(|&byte| !is_continuation(byte)) {
+            Some(start) => {
+                self.line.truncate(start);
+                true
+            }
+            None => { // only stray continuation bytes left
+                let erased = !self.line.is_empty();
+                self.line.clear();
+                erased
+            }
+        }
+    }
+
+    fn echo(&self, output: &mut impl Write, text: &str) {
+        if self.echo {
+            let _ = output.write_str(text);
+        }
+    }
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        LineDiscipline::new()
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+// bytes of the UTF-8 character starting with 'byte', 1 for anything that can't start one
+fn char_len(byte: u8) -> usize {
+    match byte.leading_ones() {
+        len @ 2..=4 => len as usize,
+        _ => 1,
+    }
+}
@@ -0,0 +1,208 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+// register offsets from the port base
+const DATA: u16 = 0; // divisor low byte while DLAB is set
+const INTERRUPT_ENABLE: u16 = 1; // divisor high byte while DLAB is set
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+const DLAB: u8 = 1 << 7;
+const DATA_READY: u8 = 1 << 0;
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+const RECEIVE_INTERRUPT: u8 = 1 << 0;
+const FIFO_ENABLE_AND_CLEAR: u8 = 0b111;
+const DTR_RTS_OUT2: u8 = 0b1011; // OUT2 gates the interrupt line on PCs
+
+pub const BASE_CLOCK: u32 = 115_200; // baud rate with divisor 1
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark, // always 1
+    Space, // always 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two, // 1.5 with five data bits
+}
+
+// received bytes that raise the receive interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoThreshold {
+    Bytes1,
+    Bytes4,
+    Bytes8,
+    Bytes14,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+    pub baud_rate: u32, // has to divide BASE_CLOCK
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub fifo_threshold: FifoThreshold,
+}
+
+impl Default for UartConfig {
+    // 38400 8N1, what the ports used before they were configurable
+    fn default() -> Self {
+        UartConfig {
+            baud_rate: 38_400,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            fifo_threshold: FifoThreshold::Bytes14,
+        }
+    }
+}
+
+impl UartConfig {
+    fn divisor(&self) -> Option<u16> {
+        if self.baud_rate == 0 || !BASE_CLOCK.is_multiple_of(self.baud_rate) {
+            return None;
+        }
+        u16::try_from(BASE_CLOCK / self.baud_rate).ok()
+    }
+
+    fn line_control(&self) -> u8 {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 0b00,
+            DataBits::Six => 0b01,
+            DataBits::Seven => 0b10,
+            DataBits::Eight => 0b11,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1 << 2,
+        };
+        let parity = match self.parity {
+            Parity::None => 0b000 << 3,
+            Parity::Odd => 0b001 << 3,
+            Parity::Even => 0b011 << 3,
+            Parity::Mark => 0b101 << 3,
+            Parity::Space => 0b111 << 3,
+        };
+        data_bits | stop_bits | parity
+    }
+
+    fn fifo_control(&self) -> u8 {
+        let threshold = match self.fifo_threshold {
+            FifoThreshold::Bytes1 => 0b00,
+            FifoThreshold::Bytes4 => 0b01,
+            FifoThreshold::Bytes8 => 0b10,
+            FifoThreshold::Bytes14 => 0b11,
+        };
+        FIFO_ENABLE_AND_CLEAR | threshold << 6
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    NotPresent,
+    InvalidBaudRate(u32),
+    Busy, // this CPU is already using the port (e.g. interrupted while writing to it)
+}
+
+// 16550 compatible UART
+#[derive(Debug)]
+pub struct Uart {
+    base: u16,
+    config: UartConfig,
+}
+
+impl Uart {
+    // checks the scratch register holds what's written to it, reads of an empty I/O port give 0xff
+    /// # Safety
+    /// writing to 'base + 7' can't have side effects on whatever device is there
+    pub unsafe fn probe(base: u16) -> bool {
+        let mut scratch: Port<u8> = Port::new(base + SCRATCH);
+        [0x55, 0xaa].into_iter().all(|value| unsafe {
+            scratch.write(value);
+            scratch.read() == value
+        })
+    }
+
+    /// # Safety
+    /// 'base' has to be the base of a UART
+    pub unsafe fn new(base: u16, config: UartConfig) -> Result<Uart, UartError> {
+        let mut uart = Uart { base, config };
+        uart.configure(config)?;
+        Ok(uart)
+    }
+
+    pub fn config(&self) -> UartConfig {
+        self.config
+    }
+
+    // reprograms line settings and FIFO, leaves the receive interrupt enabled
+    pub fn configure(&mut self, config: UartConfig) -> Result<(), UartError> {
+        let divisor = config.divisor().ok_or(UartError::InvalidBaudRate(config.baud_rate))?;
+        unsafe {
+            self.register(INTERRUPT_ENABLE).write(0);
+            self.register(LINE_CONTROL).write(DLAB);
+            self.register(DATA).write(divisor as u8);
+            self.register(INTERRUPT_ENABLE).write((divisor >> 8) as u8);
+            self.register(LINE_CONTROL).write(config.line_control());
+            self.register(FIFO_CONTROL).write(config.fifo_control());
+            self.register(MODEM_CONTROL).write(DTR_RTS_OUT2);
+            self.register(INTERRUPT_ENABLE).write(RECEIVE_INTERRUPT);
+        }
+        self.config = config;
+        Ok(())
+    }
+
+    // waits until the transmitter takes the byte, sent as is
+    pub fn send(&mut self, byte: u8) {
+        while !self.line_status(TRANSMIT_EMPTY) {
+            core::hint::spin_loop();
+        }
+        unsafe { self.register(DATA).write(byte) };
+    }
+
+    pub fn try_receive(&mut self) -> Option<u8> {
+        unsafe { receive_at(self.base) }
+    }
+
+    fn line_status(&mut self, bit: u8) -> bool {
+        unsafe { self.register(LINE_STATUS).read() & bit != 0 }
+    }
+
+    fn register(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+}
+
+// reads a received byte without going through a 'Uart', so interrupt handlers don't need its lock
+/// # Safety
+/// there has to be an initialized UART at 'base', an absent one reads as always having data
+pub(super) unsafe fn receive_at(base: u16) -> Option<u8> {
+    let mut line_status: Port<u8> = Port::new(base + LINE_STATUS);
+    let mut data: Port<u8> = Port::new(base + DATA);
+    unsafe { (line_status.read() & DATA_READY != 0).then(|| data.read()) }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+pub mod percpu;
+mod trampoline;
+
+pub use percpu::{cpu_id, PerCpu};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions;
+
+use crate::interrupts::{self, apic, pit, InterruptController};
+use crate::memory::stack;
+use crate::task::executor::Spawner;
+use crate::{acpi, gdt, thread};
+use trampoline::Trampoline;
+
+pub const MAX_CPUS: usize = 16; // per-CPU tables (e.g. thread schedulers) are sized for this many
+
+const AP_STACK_PAGES: usize = 16;
+const STARTUP_TIMEOUT_MS: u32 = 100; // per startup IPI
+
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+// runtime parked application processors should join, bumped generation tells them there's a new one
+static WORKERS: Mutex<(u64, Option<Spawner>)> = Mutex::new((0, None));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    NoApic, // needs local APIC for IPIs and the MADT for the list of processors
+    TrampolineFailed,
+}
+
+// starts every enabled processor listed in the MADT (INIT-SIPI-SIPI), they park until 'start_workers' is called
+// needs acpi::init and interrupts::init_apic, returns number of CPUs online (including the bootstrap processor)
+pub fn init() -> Result<usize, SmpError> {
+    let (Some(info), Some(local_apic)) = (acpi::info(), apic::local_apic()) else {
+        return Err(SmpError::NoApic);
+    };
+    if interrupts::controller() != InterruptController::Apic {
+        return Err(SmpError::NoApic);
+    }
+    let bsp_apic_id = local_apic.id();
+    percpu::init(percpu::allocate(0, bsp_apic_id));
+
+    let trampoline = Trampoline::install().map_err(|_| SmpError::TrampolineFailed)?;
+    let application_processors = info.processors
+        .iter()
+        .filter(|processor| processor.enabled && processor.apic_id != bsp_apic_id)
+        .take(MAX_CPUS - 1);
+    for processor in application_processors {
+        let id = cpu_count();
+        if !start_ap(&trampoline, id, processor.apic_id) {
+            log::warn!("CPU with APIC id {} didn't start", processor.apic_id);
+        }
+    }
+    Ok(cpu_count())
+}
+
+pub fn cpu_count() -> usize {
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+// makes every parked application processor run a worker of the executor behind 'spawner' until its next shutdown
+// processors park again afterwards, this has to be called again for the next run
+pub fn start_workers(spawner: &Spawner) {
+    let mut workers = WORKERS.lock();
+    workers.0 += 1;
+    workers.1 = Some(spawner.clone());
+}
+
+fn start_ap(trampoline: &Trampoline, id: usize, apic_id: u8) -> bool {
+    let local_apic = apic::local_apic().expect("checked by init");
+    // never freed, the processor runs on it forever
+    let Ok(stack) = stack::allocate_stack(AP_STACK_PAGES) else {
+        return false;
+    };
+    trampoline.prepare(stack.top(), ap_main, percpu::allocate(id, apic_id));
+
+    let online = cpu_count();
+    local_apic.send_init(apic_id);
+    pit::sleep_ms(10);
+    // second startup IPI only matters if the first one got lost
+    for _ in 0..2 {
+        local_apic.send_startup(apic_id, trampoline.page());
+        for _ in 0..STARTUP_TIMEOUT_MS {
+            if cpu_count() > online {
+                return true;
+            }
+            pit::sleep_ms(1);
+        }
+    }
+    if trampoline.withdraw() {
+        return false; // never started, if it does later it halts in the trampoline
+    }
+    // started just now, it runs on the prepared stack and data, so they can't be handed to the next processor yet
+    while cpu_count() == online {
+        pit::sleep_ms(1);
+    }
+    true
+}
+
+// first Rust code on an application processor, called by the trampoline
+extern "sysv64" fn ap_main(cpu: &'static PerCpu) -> ! {
+    percpu::init(cpu);
+    gdt::init_ap();
+    interrupts::init_ap();
+    thread::init();
+    CPUS_ONLINE.fetch_add(1, Ordering::AcqRel);
+    instructions::interrupts::enable();
+    park()
+}
+
+// waits for 'start_workers', local APIC timer wakes the CPU up to check
+fn park() -> ! {
+    let mut joined = 0;
+    loop {
+        let next = {
+            let workers = WORKERS.lock();
+            (workers.0 != joined).then(|| (workers.0, workers.1.clone()))
+        };
+        match next {
+            Some((generation, Some(spawner))) => {
+                joined = generation;
+                spawner.new_worker().run();
+            }
+            _ => instructions::hlt(),
+        }
+    }
+}
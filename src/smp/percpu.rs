@@ -0,0 +1,33 @@
+use alloc::boxed::Box;
+use x86_64::{registers::model_specific::GsBase, VirtAddr};
+
+// data owned by a single CPU, reachable through its GS base
+#[derive(Debug)]
+pub struct PerCpu {
+    pub id: usize, // 0 is the bootstrap processor, application processors are numbered in startup order
+    pub apic_id: u8,
+}
+
+// None until 'smp::init' ran (or on a CPU that wasn't started through it)
+pub fn current() -> Option<&'static PerCpu> {
+    let base = GsBase::read();
+    match base.is_null() {
+        true => None,
+        false => Some(unsafe { &*base.as_ptr() }),
+    }
+}
+
+// CPU executing this, 0 before 'smp::init'
+pub fn cpu_id() -> usize {
+    current().map_or(0, |cpu| cpu.id)
+}
+
+// leaked, GS base keeps pointing at it
+pub(super) fn allocate(id: usize, apic_id: u8) -> &'static PerCpu {
+    Box::leak(Box::new(PerCpu { id, apic_id }))
+}
+
+// has to run on the CPU 'cpu' belongs to
+pub(super) fn init(cpu: &'static PerCpu) {
+    GsBase::write(VirtAddr::from_ptr(cpu));
+}
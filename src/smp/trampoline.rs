@@ -0,0 +1,196 @@
+use core::arch::global_asm;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    registers::control::{Cr0, Cr3, Cr4, Cr4Flags},
+    structures::paging::{mapper::MapToError, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+use super::percpu::PerCpu;
+use crate::memory::{self, PAGE_SIZE};
+
+const LOW_MEMORY_END: u64 = 0x9f000; // startup IPI can only point below 1 MiB, EBDA and video memory start here
+const STACK_SPACE: usize = 64; // top of the trampoline page is its stack in protected mode
+
+// first code an application processor runs after the startup IPI, copied into a page below 1 MiB
+// real mode -> protected mode -> long mode with the kernel's page table, then calls the entry point on its own stack
+// page address is only known at runtime, so ebx holds it from real mode on and the far jump targets get relocated
+// the entry point is taken out of the data block (swapped with zero), a processor that finds none there halts
+global_asm!(r#"
+.pushsection .text.ap_trampoline, "ax"
+.global ap_trampoline_start
+.global ap_trampoline_gdt_ptr
+.global ap_trampoline_pm_far
+.global ap_trampoline_data
+.global ap_trampoline_end
+
+.code16
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+    movzx %ax, %ebx
+    shl $4, %ebx
+    lgdtl ap_trampoline_gdt_ptr - ap_trampoline_start
+    mov %cr0, %eax
+    or $1, %eax
+    mov %eax, %cr0
+    ljmpl *(ap_trampoline_pm_far - ap_trampoline_start)
+
+.code32
+ap_trampoline_32:
+    mov $0x10, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    lea 4096(%ebx), %esp
+    mov (ap_trampoline_cr4 - ap_trampoline_start)(%ebx), %eax
+    mov %eax, %cr4
+    mov (ap_trampoline_cr3 - ap_trampoline_start)(%ebx), %eax
+    mov %eax, %cr3
+    mov $0xc0000080, %ecx
+    rdmsr
+    or $0x900, %eax
+    wrmsr
+    mov (ap_trampoline_cr0 - ap_trampoline_start)(%ebx), %eax
+    mov %eax, %cr0
+    lea (ap_trampoline_64 - ap_trampoline_start)(%ebx), %eax
+    push $0x18
+    push %eax
+    lret
+
+.code64
+ap_trampoline_64:
+    xor %ax, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    mov %ebx, %ebx
+    xor %eax, %eax
+    xchg (ap_trampoline_entry - ap_trampoline_start)(%rbx), %rax
+    test %rax, %rax
+    jz 1f
+    mov (ap_trampoline_stack - ap_trampoline_start)(%rbx), %rsp
+    mov (ap_trampoline_arg - ap_trampoline_start)(%rbx), %rdi
+    call *%rax
+1:
+    hlt
+    jmp 1b
+
+.align 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00cf9a000000ffff
+    .quad 0x00cf92000000ffff
+    .quad 0x00af9a000000ffff
+ap_trampoline_gdt_ptr:
+    .word 31
+    .long ap_trampoline_gdt - ap_trampoline_start
+ap_trampoline_pm_far:
+    .long ap_trampoline_32 - ap_trampoline_start
+    .word 0x08
+
+.align 8
+ap_trampoline_data:
+ap_trampoline_cr0:
+    .quad 0
+ap_trampoline_cr3:
+    .quad 0
+ap_trampoline_cr4:
+    .quad 0
+ap_trampoline_stack:
+    .quad 0
+ap_trampoline_entry:
+    .quad 0
+ap_trampoline_arg:
+    .quad 0
+ap_trampoline_end:
+.popsection
+"#, options(att_syntax));
+
+unsafe extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_gdt_ptr: u8;
+    static ap_trampoline_pm_far: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+// same layout as the block at 'ap_trampoline_data'
+#[repr(C)]
+struct TrampolineData {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    stack_top: u64,
+    entry: u64,
+    arg: u64,
+}
+
+pub(super) type Entry = extern "sysv64" fn(&'static PerCpu) -> !;
+
+// startup code installed in low memory, shared by all application processors, so they have to be started one by one
+pub(super) struct Trampoline {
+    base: PhysAddr, // identity mapped
+}
+
+impl Trampoline {
+    pub(super) fn install() -> Result<Trampoline, MapToError<Size4KiB>> {
+        let size = offset_of(&raw const ap_trampoline_end);
+        assert!(size <= PAGE_SIZE - STACK_SPACE, "AP trampoline doesn't fit into a page");
+
+        let base = memory::with_vmm(|vmm| vmm.allocate_identity_page(PhysAddr::new(LOW_MEMORY_END)))?;
+        let trampoline = Trampoline { base };
+        unsafe {
+            ptr::copy_nonoverlapping(&raw const ap_trampoline_start, trampoline.at(0), size);
+            // offsets assembled into the GDT pointer and the far jump become physical addresses
+            trampoline.relocate(offset_of(&raw const ap_trampoline_gdt_ptr) + 2);
+            trampoline.relocate(offset_of(&raw const ap_trampoline_pm_far));
+        }
+        Ok(trampoline)
+    }
+
+    // vector of the startup IPI
+    pub(super) fn page(&self) -> u8 {
+        (self.base.as_u64() / PAGE_SIZE as u64) as u8
+    }
+
+    // next processor started will switch to the current page table and call 'entry(arg)' on 'stack_top'
+    pub(super) fn prepare(&self, stack_top: VirtAddr, entry: Entry, arg: &'static PerCpu) {
+        let cr3 = Cr3::read().0.start_address().as_u64();
+        assert!(cr3 < 1 << 32, "level 4 table has to be below 4 GiB, it's loaded in protected mode");
+        let data = TrampolineData {
+            cr0: Cr0::read_raw(),
+            cr3,
+            cr4: Cr4::read_raw() & !Cr4Flags::PCID.bits(), // PCID can only be enabled in long mode
+            stack_top: stack_top.as_u64(),
+            entry: entry as usize as u64,
+            arg: arg as *const PerCpu as u64,
+        };
+        unsafe { ptr::write_volatile(self.at(offset_of(&raw const ap_trampoline_data)).cast(), data) };
+    }
+
+    // takes the prepared entry point back, so a processor starting late halts instead of running on this data
+    // false if a processor already took it, it's running on the prepared stack then
+    pub(super) fn withdraw(&self) -> bool {
+        let offset = offset_of(&raw const ap_trampoline_data) + core::mem::offset_of!(TrampolineData, entry);
+        let entry = unsafe { AtomicU64::from_ptr(self.at(offset).cast()) };
+        entry.swap(0, Ordering::AcqRel) != 0
+    }
+
+    fn at(&self, offset: usize) -> *mut u8 {
+        (self.base.as_u64() as usize + offset) as *mut u8
+    }
+
+    unsafe fn relocate(&self, offset: usize) {
+        let field = self.at(offset).cast::<u32>();
+        unsafe { field.write_unaligned(field.read_unaligned() + self.base.as_u64() as u32) };
+    }
+}
+
+// position of a trampoline symbol relative to its start
+fn offset_of(symbol: *const u8) -> usize {
+    symbol as usize - &raw const ap_trampoline_start as usize
+}
@@ -1,82 +1,564 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
-use crossbeam_queue::ArrayQueue;
+use super::{join::JoinHandle, timer, LocalRawTask, LocalTask, Priority, RawTask, Task, TaskHeader, TaskId};
+use crate::time::{self, Instant};
+use alloc::{collections::BTreeMap, rc::Rc, string::String, sync::Arc, task::Wake, vec::Vec};
+use core::cell::RefCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Poll, Waker};
+use core::time::Duration;
+use crossbeam_queue::{ArrayQueue, SegQueue};
+use spin::{Mutex, RwLock};
 use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
+const INITIAL_QUEUE_CAPACITY: usize = 64;
+const POLL_BUDGET: u32 = 4; // polls a task gets per round, further wakeups wait for the next round
+const AGING_ROUNDS: u32 = 8; // rounds a level with ready tasks can be passed over before it's served anyway
+const NOT_RUNNING: u64 = u64::MAX; // value of 'Worker::running' between polls
+
+// one worker of a runtime, normally one per core (see smp::start_workers)
+// Send tasks are shared by all workers: a woken task is queued at the worker that polled it last,
+// a worker that runs out of ready tasks steals them from the others' queues
+// tasks spawned with 'spawn_local' are only ever polled by the executor they were spawned on
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>, // Arc implements reference counting, it will be shared between executor and wakers
-    waker_cache: BTreeMap<TaskId, Waker>,
+    worker: Arc<Worker>,
+    worker_index: usize,
+    local_tasks: BTreeMap<TaskId, LocalRawTask>,
+    // one queue per priority, Arc implements reference counting, they will be shared between executor and wakers
+    local_queues: [Arc<WakeQueue>; Priority::COUNT],
+    local_spawned: Rc<RefCell<Vec<LocalRawTask>>>, // tasks spawned through a LocalSpawner
+    starved_rounds: [u32; Priority::COUNT],
+    polls: BTreeMap<TaskId, u32>, // polls in current round
+    deferred: Vec<TaskId>, // tasks over budget (or busy on another worker), queued again when round ends
+    shared: Arc<Shared>,
+}
+
+// part of the runtime reachable from every worker and through Spawners
+struct Shared {
+    injector: SegQueue<RawTask>, // tasks spawned through a Spawner, picked up by the next worker looking for work
+    tasks: Mutex<BTreeMap<TaskId, RawTask>>, // Send tasks, except the ones being polled right now
+    workers: Arc<Workers>, // separate Arc, so wakers stored inside 'tasks' don't keep Shared alive
+    shutdown: AtomicBool,
+    active: AtomicUsize, // workers inside 'run', the last one to stop drops the shared tasks
+    registry: Mutex<BTreeMap<TaskId, Arc<TaskHeader>>>, // headers of live tasks, for snapshots
+}
+
+// wakers read it from interrupt handlers, so it's only written with interrupts disabled
+type Workers = RwLock<Vec<Arc<Worker>>>;
+
+struct Worker {
+    queues: [WakeQueue; Priority::COUNT], // ready Send tasks, one queue per priority
+    running: AtomicU64, // id of the task being polled
+}
+
+// cloneable handle for spawning tasks onto a running executor (e.g. from inside a task or another core)
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>,
+}
+
+impl Spawner {
+    pub fn spawn<T: Send + 'static>(&self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.shared.injector.push(task); // lock-free and unbounded, so spawning never blocks or fails
+        handle
+    }
+
+    // makes 'run' of every worker cancel all tasks and return, takes effect once the currently polled tasks yield
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+    }
+
+    // same as 'Executor::snapshot', usable from inside tasks
+    pub fn snapshot(&self) -> Vec<TaskInfo> {
+        self.shared.snapshot()
+    }
+
+    // another executor sharing this one's Send tasks, meant to be run on another core
+    pub fn new_worker(&self) -> Executor {
+        Executor::with_shared(self.shared.clone())
+    }
+}
+
+// spawns onto one particular executor, so it isn't Send itself
+#[derive(Clone)]
+pub struct LocalSpawner {
+    spawned: Rc<RefCell<Vec<LocalRawTask>>>,
+}
+
+impl LocalSpawner {
+    pub fn spawn_local<T: 'static>(&self, task: LocalTask<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.spawned.borrow_mut().push(task);
+        handle
+    }
+}
+
+impl Shared {
+    fn snapshot(&self) -> Vec<TaskInfo> {
+        let running: Vec<u64> = self.workers.read().iter().map(|worker| worker.running.load(Ordering::Relaxed)).collect();
+        self.registry
+            .lock()
+            .iter()
+            .map(|(task_id, header)| TaskInfo::new(*task_id, header, running.contains(&task_id.0)))
+            .collect()
+    }
+
+    fn is_alive(&self, task_id: TaskId) -> bool {
+        self.registry.lock().contains_key(&task_id)
+    }
+
+    // every task is queued at most once, but a single worker's queue may end up holding all of them
+    fn reserve(&self, level: usize, capacity: usize) {
+        for worker in self.workers.read().iter() {
+            worker.queues[level].reserve(capacity);
+        }
+    }
+
+    // called by the last worker to stop, nobody is polling shared tasks anymore
+    fn cancel_shared(&self) {
+        let tasks = core::mem::take(&mut *self.tasks.lock());
+        {
+            let mut registry = self.registry.lock();
+            for (task_id, task) in &tasks {
+                task.header.queued.store(true, Ordering::Release); // same as 'finish_task', nothing gets queued anymore
+                registry.remove(task_id);
+            }
+        }
+        drop(tasks); // drops futures (outside of the lock), wakes whoever awaits them
+        while self.injector.pop().is_some() {}
+        for worker in self.workers.read().iter() {
+            for queue in &worker.queues {
+                while queue.pop().is_some() {}
+            }
+        }
+    }
 }
 
 impl Executor {
     pub fn new() -> Self {
+        Executor::with_shared(Arc::new(Shared {
+            injector: SegQueue::new(),
+            tasks: Mutex::new(BTreeMap::new()),
+            workers: Arc::new(RwLock::new(Vec::new())),
+            shutdown: AtomicBool::new(false),
+            active: AtomicUsize::new(0),
+            registry: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    fn with_shared(shared: Arc<Shared>) -> Self {
+        let capacity = INITIAL_QUEUE_CAPACITY.max(shared.registry.lock().len());
+        let worker = Arc::new(Worker {
+            queues: core::array::from_fn(|_| WakeQueue::new(capacity)),
+            running: AtomicU64::new(NOT_RUNNING),
+        });
+        let worker_index = interrupts::without_interrupts(|| {
+            let mut workers = shared.workers.write();
+            workers.push(worker.clone());
+            workers.len() - 1
+        });
+
         Executor {
-            tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
+            worker,
+            worker_index,
+            local_tasks: BTreeMap::new(),
+            local_queues: core::array::from_fn(|_| Arc::new(WakeQueue::new(INITIAL_QUEUE_CAPACITY))),
+            local_spawned: Rc::new(RefCell::new(Vec::new())),
+            starved_rounds: [0; Priority::COUNT],
+            polls: BTreeMap::new(),
+            deferred: Vec::new(),
+            shared,
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
+    pub fn spawner(&self) -> Spawner {
+        Spawner { shared: self.shared.clone() }
+    }
+
+    pub fn local_spawner(&self) -> LocalSpawner {
+        LocalSpawner { spawned: self.local_spawned.clone() }
+    }
+
+    pub fn spawn<T: Send + 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.insert_task(task);
+        handle
+    }
+
+    // task is only polled by this executor, so its future doesn't have to be Send
+    pub fn spawn_local<T: 'static>(&mut self, task: LocalTask<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
+        self.insert_local_task(task);
+        handle
+    }
+
+    fn insert_task(&mut self, task: RawTask) {
+        let (task_id, priority) = (task.id, task.priority());
+        task.header.owner.store(self.worker_index, Ordering::Relaxed);
+        task.header.queued.store(true, Ordering::Release);
+        let count = {
+            let mut registry = self.shared.registry.lock();
+            registry.insert(task_id, task.header.clone());
+            registry.len()
+        };
+        if self.shared.tasks.lock().insert(task_id, task).is_some() {
             panic!("task with same ID already in tasks"); // should never happend
         }
-        self.task_queue.push(task_id).expect("queue full");
+
+        self.shared.reserve(priority.index(), count);
+        self.worker.queues[priority.index()].push(task_id);
     }
 
-    pub fn run(&mut self) -> ! {
-        loop {
+    fn insert_local_task(&mut self, task: LocalRawTask) {
+        let (task_id, priority) = (task.id, task.priority());
+        task.header.queued.store(true, Ordering::Release);
+        self.shared.registry.lock().insert(task_id, task.header.clone());
+        if self.local_tasks.insert(task_id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+
+        // every task is queued at most once, so a queue as big as the number of tasks never overflows
+        let queue = &self.local_queues[priority.index()];
+        queue.reserve(self.local_tasks.len());
+        queue.push(task_id);
+    }
+
+    fn finish_task(&mut self, header: &TaskHeader, task_id: TaskId) {
+        // stale wakers (e.g. in timers) may still fire, a dead task looks queued forever so they push nothing
+        header.queued.store(true, Ordering::Release);
+        self.shared.registry.lock().remove(&task_id);
+    }
+
+    // length, capacity and the most ids ever queued at once for every priority level of this worker's queues
+    // (the ones Send tasks go to, spawn_local ones aren't included)
+    pub fn queue_stats(&self) -> [QueueStats; Priority::COUNT] {
+        core::array::from_fn(|level| self.worker.queues[level].stats())
+    }
+
+    // live tasks of all workers ordered by id, tasks spawned through a Spawner show up once a worker picked them up
+    pub fn snapshot(&self) -> Vec<TaskInfo> {
+        self.shared.snapshot()
+    }
+
+    // nothing ready at 'level' here, and nothing to steal from other workers
+    fn level_empty(&self, level: usize) -> bool {
+        self.local_queues[level].is_empty()
+            && self.shared.workers.read().iter().all(|worker| worker.queues[level].is_empty())
+    }
+
+    // own queues first, then steals from the other workers (starting from the next one, so victims are spread)
+    fn next_ready(&self, level: usize) -> Option<TaskId> {
+        if let Some(task_id) = self.local_queues[level].pop().or_else(|| self.worker.queues[level].pop()) {
+            return Some(task_id);
+        }
+        let workers = self.shared.workers.read();
+        (1..workers.len())
+            .map(|offset| &workers[(self.worker_index + offset) % workers.len()])
+            .find_map(|victim| victim.queues[level].pop())
+    }
+
+    // runs until shutdown is requested through a Spawner
+    // a worker joining while others are still stopping sees the shutdown and returns right away
+    pub fn run(&mut self) {
+        if self.shared.active.fetch_add(1, Ordering::AcqRel) == 0 {
+            self.shared.shutdown.store(false, Ordering::Release); // first worker of a new run, the last shutdown is over
+        }
+        while !self.is_shutting_down() {
+            timer::wake_expired();
+            self.take_spawned_tasks();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
+        self.cancel_all();
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shared.shutdown.load(Ordering::Acquire)
+    }
+
+    // drops local tasks, the last worker to stop drops the shared ones too, their JoinHandles resolve to Err(Cancelled)
+    // shutdown stays requested until the next 'run' starts with no worker active, so the executor can be run again
+    fn cancel_all(&mut self) {
+        {
+            let mut registry = self.shared.registry.lock();
+            for (task_id, task) in &self.local_tasks {
+                task.header.queued.store(true, Ordering::Release); // same as 'finish_task', nothing gets queued anymore
+                registry.remove(task_id);
+            }
+        }
+        self.local_tasks.clear(); // drops futures, wakes whoever awaits them
+        self.local_spawned.borrow_mut().clear();
+        for queue in &self.local_queues {
+            while queue.pop().is_some() {}
+        }
+        self.polls.clear();
+        self.deferred.clear();
+
+        if self.shared.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.cancel_shared();
+        }
+    }
+
+    fn take_spawned_tasks(&mut self) {
+        while let Some(task) = self.shared.injector.pop() {
+            self.insert_task(task);
+        }
+        let spawned = core::mem::take(&mut *self.local_spawned.borrow_mut());
+        for task in spawned {
+            self.insert_local_task(task);
+        }
     }
 
     fn sleep_if_idle(&self) {
         interrupts::disable(); // to avoid race condition here after 'if'
-        if self.task_queue.is_empty() {
+        // timer interrupt ends the halt, expired timers are then woken on the next loop iteration
+        // (work queued by other cores is noticed on the next interrupt too)
+        let idle = (0..Priority::COUNT).all(|level| self.level_empty(level))
+            && self.shared.injector.is_empty()
+            && self.local_spawned.borrow().is_empty()
+            && !self.is_shutting_down();
+        if idle && !timer::has_expired() {
             enable_and_hlt(); // enables interrupts and halts
         } else {
             interrupts::enable();
         }
     }
 
+    // one scheduling round: the highest non-empty level is served, plus any lower level that waited too long
     fn run_ready_tasks(&mut self) {
-        while let Some(task_id) = self.task_queue.pop() {
-            let task = match self.tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-
-            let waker = self.waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, self.task_queue.clone())); // create waker if it wasnt cached
-
-            let mut context = Context::from_waker(waker);
-
-            match task.poll(&mut context) {
-                Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    self.tasks.remove(&task_id);
-                    self.waker_cache.remove(&task_id);
-                }
-                Poll::Pending => {}
+        let highest = (0..Priority::COUNT).find(|&level| !self.level_empty(level));
+        for level in 0..Priority::COUNT {
+            if self.level_empty(level) {
+                self.starved_rounds[level] = 0;
+            } else if Some(level) == highest || self.starved_rounds[level] >= AGING_ROUNDS {
+                self.starved_rounds[level] = 0;
+                self.run_level(level);
+            } else {
+                self.starved_rounds[level] += 1;
+            }
+        }
+
+        for task_id in core::mem::take(&mut self.deferred) {
+            // still marked as queued
+            if let Some(task) = self.local_tasks.get(&task_id) {
+                self.local_queues[task.priority().index()].push(task_id);
+            } else if let Some(header) = self.shared.registry.lock().get(&task_id) {
+                self.worker.queues[header.priority.index()].push(task_id);
+            }
+        }
+        self.polls.clear();
+    }
+
+    fn run_level(&mut self, level: usize) {
+        while let Some(task_id) = self.next_ready(level) {
+            if self.is_shutting_down() {
+                break;
+            }
+
+            let polls = self.polls.entry(task_id).or_insert(0);
+            if *polls >= POLL_BUDGET {
+                self.deferred.push(task_id); // keeps waking itself, let the others run first
+                continue;
+            }
+            *polls += 1;
+
+            if self.local_tasks.contains_key(&task_id) {
+                self.poll_local_task(task_id, level);
+            } else {
+                self.poll_task(task_id);
+            }
+        }
+    }
+
+    fn poll_local_task(&mut self, task_id: TaskId, level: usize) {
+        let task = self.local_tasks.get_mut(&task_id).expect("checked by caller");
+        // cleared before polling, so a wakeup during the poll queues the task again
+        task.header.queued.store(false, Ordering::Release);
+        let queue = &self.local_queues[level];
+
+        self.worker.running.store(task_id.0, Ordering::Relaxed);
+        let result = task.poll(|header| TaskWaker::new(task_id, header.clone(), WakeTarget::Local(queue.clone())));
+        self.worker.running.store(NOT_RUNNING, Ordering::Relaxed);
+        if result.is_ready() {
+            let task = self.local_tasks.remove(&task_id).expect("polled above");
+            self.finish_task(&task.header, task_id); // task done (or aborted) -> remove it
+        }
+    }
+
+    fn poll_task(&mut self, task_id: TaskId) {
+        let Some(mut task) = self.shared.tasks.lock().remove(&task_id) else {
+            if self.shared.is_alive(task_id) {
+                self.deferred.push(task_id); // another worker is polling it right now, try again next round
+            }
+            return; // otherwise task no longer exists
+        };
+        task.header.owner.store(self.worker_index, Ordering::Relaxed);
+        task.header.queued.store(false, Ordering::Release);
+        let workers = &self.shared.workers;
+
+        self.worker.running.store(task_id.0, Ordering::Relaxed);
+        let result = task.poll(|header| TaskWaker::new(task_id, header.clone(), WakeTarget::Shared(workers.clone())));
+        self.worker.running.store(NOT_RUNNING, Ordering::Relaxed);
+        match result {
+            Poll::Ready(()) => self.finish_task(&task.header, task_id),
+            Poll::Pending => {
+                self.shared.tasks.lock().insert(task_id, task);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub high_water_mark: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Running, // being polled right now by one of the workers
+    Ready,   // sits in a wake queue
+    Waiting, // waits for a waker
+}
+
+// copy of a task's counters at the time of the snapshot
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: Option<String>,
+    pub priority: Priority,
+    pub state: TaskState,
+    pub polls: u64,
+    pub poll_time: Duration, // total time spent inside poll, zero without calibrated TSC
+    pub spawned_at: Instant,
+    pub last_woken: Option<Instant>,
+    pub last_polled: Option<Instant>,
+}
+
+impl TaskInfo {
+    // column titles matching the Display output, for printing a ps-like table
+    pub const HEADER: &'static str = "   ID NAME             PRIO   STATE        POLLS   TIME(us)";
+
+    fn new(task_id: TaskId, header: &TaskHeader, running: bool) -> Self {
+        let instant = |nanos: &AtomicU64| match nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Instant::from_nanos(nanos)),
+        };
+        let state = if running {
+            TaskState::Running
+        } else if header.queued.load(Ordering::Acquire) {
+            TaskState::Ready
+        } else {
+            TaskState::Waiting
+        };
+
+        TaskInfo {
+            id: task_id.0,
+            name: header.name.clone(),
+            priority: header.priority,
+            state,
+            polls: header.polls.load(Ordering::Relaxed),
+            poll_time: time::cycles_to_duration(header.poll_cycles.load(Ordering::Relaxed)),
+            spawned_at: header.spawned_at,
+            last_woken: instant(&header.last_woken),
+            last_polled: instant(&header.last_polled),
+        }
+    }
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.name.as_deref().unwrap_or("-");
+        let priority = alloc::format!("{:?}", self.priority);
+        let state = alloc::format!("{:?}", self.state);
+        write!(f, "{:>5} {:<16} {:<6} {:<8} {:>9} {:>10}",
+            self.id, name, priority, state, self.polls, self.poll_time.as_micros())
+    }
+}
+
+// ArrayQueue doesn't allocate on push (wakers run in interrupt handlers), but it can't grow in place,
+// so it gets swapped for a bigger one when the executor has more tasks than it can hold
+struct WakeQueue {
+    queue: RwLock<ArrayQueue<TaskId>>,
+    high_water_mark: AtomicUsize,
+}
+
+impl WakeQueue {
+    fn new(capacity: usize) -> Self {
+        WakeQueue {
+            queue: RwLock::new(ArrayQueue::new(capacity)),
+            high_water_mark: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, task_id: TaskId) {
+        let queue = self.queue.read();
+        queue.push(task_id).expect("wake queue smaller than number of tasks");
+        self.high_water_mark.fetch_max(queue.len(), Ordering::Relaxed);
+    }
+
+    fn pop(&self) -> Option<TaskId> {
+        self.queue.read().pop()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.read().is_empty()
+    }
+
+    // makes room for at least 'capacity' ids, called from task context only
+    fn reserve(&self, capacity: usize) {
+        if self.queue.read().capacity() >= capacity {
+            return;
+        }
+
+        let bigger = ArrayQueue::new((capacity * 2).next_power_of_two());
+        // interrupts are off while the write lock is held, so a waker can't spin on it forever
+        interrupts::without_interrupts(|| {
+            let mut queue = self.queue.write();
+            while let Some(task_id) = queue.pop() {
+                bigger.push(task_id).expect("new queue is bigger");
             }
+            *queue = bigger;
+        });
+    }
+
+    fn stats(&self) -> QueueStats {
+        let queue = self.queue.read();
+        QueueStats {
+            len: queue.len(),
+            capacity: queue.capacity(),
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
         }
     }
 }
 
-// its jkob is to push the ID of woken task to task_queue
+// its jkob is to push the ID of woken task to a wake queue, unless it's already there
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>, // its shared between executor and wakers
+    header: Arc<TaskHeader>,
+    target: WakeTarget,
+}
+
+enum WakeTarget {
+    Local(Arc<WakeQueue>), // queue of the executor the task was spawned on
+    Shared(Arc<Workers>), // queue of whichever worker polled the task last
 }
 
 impl TaskWaker {
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        self.header.last_woken.store(Instant::now().as_nanos(), Ordering::Relaxed);
+        if self.header.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        match &self.target {
+            WakeTarget::Local(queue) => queue.push(self.task_id),
+            WakeTarget::Shared(workers) => {
+                let workers = workers.read();
+                let owner = &workers[self.header.owner.load(Ordering::Relaxed)];
+                owner.queues[self.header.priority.index()].push(self.task_id);
+            }
+        }
     }
 }
 
@@ -91,10 +573,11 @@ impl Wake for TaskWaker {
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, header: Arc<TaskHeader>, target: WakeTarget) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
-            task_queue,
+            header,
+            target,
         }))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,125 @@
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+const RUNNING: u8 = 0;
+const FINISHED: u8 = 1;
+const CANCELLED: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Cancelled, // aborted, or dropped by executor shutdown before it finished
+}
+
+// shared between a running task (writes output once) and its JoinHandle (takes it)
+pub(super) struct JoinState<T> {
+    output: Mutex<Option<T>>,
+    status: AtomicU8,
+    abort_requested: AtomicBool,
+    join_waker: AtomicWaker, // whoever awaits the JoinHandle
+    task_waker: AtomicWaker, // the task itself, so abort gets noticed without waiting for its own wakeup
+}
+
+impl<T> JoinState<T> {
+    pub(super) fn new() -> Arc<Self> {
+        Arc::new(JoinState {
+            output: Mutex::new(None),
+            status: AtomicU8::new(RUNNING),
+            abort_requested: AtomicBool::new(false),
+            join_waker: AtomicWaker::new(),
+            task_waker: AtomicWaker::new(),
+        })
+    }
+
+    fn finish(&self, status: u8) {
+        // only the first transition counts, cancelling a finished task changes nothing
+        if self.status.compare_exchange(RUNNING, status, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            self.join_waker.wake();
+        }
+    }
+}
+
+// wraps the spawned future, routes its output to JoinState and stops polling it once aborted
+// dropping it unfinished (abort, shutdown) marks the task as cancelled
+pub(super) struct Joinable<F: Future> {
+    future: F,
+    state: Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Joinable<F> {
+    pub(super) fn new(future: F, state: Arc<JoinState<F::Output>>) -> Self {
+        Joinable { future, state }
+    }
+}
+
+impl<F: Future> Future for Joinable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        // 'future' is never moved out of the pinned Joinable
+        let this = unsafe { self.get_unchecked_mut() };
+        this.state.task_waker.register(context.waker());
+        if this.state.abort_requested.load(Ordering::Acquire) {
+            return Poll::Ready(()); // executor drops the task, which drops the future
+        }
+
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match future.poll(context) {
+            Poll::Ready(output) => {
+                *this.state.output.lock() = Some(output);
+                this.state.finish(FINISHED);
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> Drop for Joinable<F> {
+    fn drop(&mut self) {
+        self.state.finish(CANCELLED);
+    }
+}
+
+// future resolving to the output of a spawned task, dropping it detaches the task (it keeps running)
+pub struct JoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(state: Arc<JoinState<T>>) -> Self {
+        JoinHandle { state }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.status.load(Ordering::Acquire) != RUNNING
+    }
+
+    // stops the task, its future (and executor's cached waker for it) is dropped the next time executor gets to it
+    // awaiting the handle afterwards gives Err(Cancelled), unless the task already finished
+    pub fn abort(&self) {
+        self.state.abort_requested.store(true, Ordering::Release);
+        self.state.task_waker.wake();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        // register before checking, so completion in between isn't missed
+        self.state.join_waker.register(context.waker());
+        match self.state.status.load(Ordering::Acquire) {
+            RUNNING => Poll::Pending,
+            FINISHED => {
+                let output = self.state.output.lock().take();
+                Poll::Ready(Ok(output.expect("JoinHandle polled after completion")))
+            }
+            _ => Poll::Ready(Err(JoinError::Cancelled)),
+        }
+    }
+}
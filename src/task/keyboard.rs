@@ -1,46 +1,281 @@
+use alloc::boxed::Box;
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
-use crate::{print, println};
+use crate::{print, ps2, vga_buffer};
+use crate::task::sync::broadcast::{self, RecvError};
 use core::{pin::Pin, task::{Poll, Context}};
-use futures_util::stream::{Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use futures_util::stream::{self, Stream, StreamExt};
+use pc_keyboard::{HandleControl, KeyboardLayout, ScancodeSet, ScancodeSet1};
 use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
 
+pub mod layout;
+
+pub use layout::Layout;
+pub use pc_keyboard::{DecodedKey, KeyCode};
+
+const EVENT_CAPACITY: usize = 64; // events a subscriber can fall behind before it misses some
 
 // using OnceCell instead of lazy_static, to ensure allocation doesnt happend in interrput handler
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 // for storing wakers
 static WAKER: AtomicWaker = AtomicWaker::new();
+// key events decoded by 'run', every subscriber gets all of them
+static EVENTS: OnceCell<broadcast::Sender<KeyEvent>> = OnceCell::uninit();
+static LAYOUT: Mutex<Layout> = Mutex::new(Layout::Us);
+static LOCKS: Mutex<LockKeys> = Mutex::new(LockKeys { caps: false, num: false, scroll: false });
 
-pub async fn print_keypress() {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Pressed,
+    Released,
+}
+
+// modifier keys held down when the event happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub gui: bool, // either Windows key
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.lshift || self.rshift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+}
+
+// toggled on every press of the lock key, shown by the keyboard LEDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockKeys {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
+
+impl LockKeys {
+    // LED byte of the 'set LEDs' keyboard command
+    pub fn leds(&self) -> u8 {
+        (self.scroll as u8) | (self.num as u8) << 1 | (self.caps as u8) << 2
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode, // physical key, independent of the layout
+    pub state: KeyState,
+    pub modifiers: Modifiers, // after this event, so pressing shift reports shift held
+    pub locks: LockKeys,
+    pub key: Option<DecodedKey>, // what the key means in the active layout, only for presses
+}
+
+// turns scancode set 1 bytes into key events, keeps track of modifiers and lock keys
+pub struct KeyDecoder {
+    scancodes: ScancodeSet1,
+    layout: Layout,
+    modifiers: Modifiers,
+    locks: LockKeys,
+    pause: bool, // pause key is sent as a hidden control key followed by num lock
+}
+
+impl KeyDecoder {
+    pub fn new(layout: Layout) -> KeyDecoder {
+        KeyDecoder {
+            scancodes: ScancodeSet1::new(),
+            layout,
+            modifiers: Modifiers::default(),
+            locks: LockKeys::default(),
+            pause: false,
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn locks(&self) -> LockKeys {
+        self.locks
+    }
+
+    // None while a multi-byte scancode is incomplete (or for bytes that aren't key events)
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        let event = self.scancodes.advance_state(byte).ok()??;
+        let pressed = match event.state {
+            pc_keyboard::KeyState::Down => true,
+            pc_keyboard::KeyState::Up => false,
+            pc_keyboard::KeyState::SingleShot => return None, // self test results and the like
+        };
+
+        let mut code = event.code;
+        match code {
+            KeyCode::LShift => self.modifiers.lshift = pressed,
+            KeyCode::RShift => self.modifiers.rshift = pressed,
+            KeyCode::LControl => self.modifiers.lctrl = pressed,
+            KeyCode::RControl => self.modifiers.rctrl = pressed,
+            KeyCode::LAlt => self.modifiers.alt = pressed,
+            KeyCode::RAltGr => self.modifiers.alt_gr = pressed,
+            KeyCode::LWin | KeyCode::RWin => self.modifiers.gui = pressed,
+            KeyCode::RControl2 => self.pause = pressed,
+            KeyCode::NumpadLock if self.pause => code = KeyCode::PauseBreak,
+            KeyCode::NumpadLock if pressed => self.locks.num = !self.locks.num,
+            KeyCode::CapsLock if pressed => self.locks.caps = !self.locks.caps,
+            KeyCode::ScrollLock if pressed => self.locks.scroll = !self.locks.scroll,
+            _ => {}
+        }
+
+        let key = pressed.then(|| self.layout.map_keycode(code, &self.layout_modifiers(), HandleControl::Ignore));
+        Some(KeyEvent {
+            code,
+            state: if pressed { KeyState::Pressed } else { KeyState::Released },
+            modifiers: self.modifiers,
+            locks: self.locks,
+            key,
+        })
+    }
+
+    // state in the form pc_keyboard layouts expect
+    fn layout_modifiers(&self) -> pc_keyboard::Modifiers {
+        pc_keyboard::Modifiers {
+            lshift: self.modifiers.lshift,
+            rshift: self.modifiers.rshift,
+            lctrl: self.modifiers.lctrl,
+            rctrl: self.modifiers.rctrl,
+            numlock: self.locks.num,
+            capslock: self.locks.caps,
+            alt_gr: self.modifiers.alt_gr,
+            rctrl2: self.pause,
+        }
+    }
+}
+
+fn events() -> &'static broadcast::Sender<KeyEvent> {
+    EVENTS.get_or_init(|| broadcast::channel(EVENT_CAPACITY).0) // sending without subscribers just drops the event
+}
+
+// key events from now on, as decoded by 'run'
+// a subscriber that falls more than EVENT_CAPACITY events behind skips the oldest ones
+pub fn subscribe() -> KeyEvents {
+    let events = stream::unfold(events().subscribe(), |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    KeyEvents { inner: Box::pin(events) }
+}
+
+pub struct KeyEvents {
+    inner: Pin<Box<dyn Stream<Item = KeyEvent> + Send>>,
+}
+
+impl Stream for KeyEvents {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+// takes effect with the next key event
+pub fn set_layout(layout: Layout) {
+    interrupts::without_interrupts(|| *LAYOUT.lock() = layout);
+}
+
+pub fn layout() -> Layout {
+    interrupts::without_interrupts(|| *LAYOUT.lock())
+}
+
+pub fn locks() -> LockKeys {
+    interrupts::without_interrupts(|| *LOCKS.lock())
+}
+
+// keyboard service: decodes scancodes from IRQ1, publishes key events and keeps the lock LEDs in sync
+pub async fn run() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore);
-    
+    let mut decoder = KeyDecoder::new(layout());
+
     // endless loop, bcs stream never returns None
     while let Some(scancode) = scancodes.next().await { // asynchronously wait for result of future
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
+        decoder.set_layout(layout());
+        let Some(event) = decoder.add_byte(scancode) else { continue };
+
+        let changed = interrupts::without_interrupts(|| {
+            let mut locks = LOCKS.lock();
+            let changed = *locks != event.locks;
+            *locks = event.locks;
+            changed
+        });
+        if changed && let Err(err) = ps2::set_keyboard_leds(event.locks.leds()).await {
+            log::warn!("failed to set keyboard LEDs: {:?}", err);
+        }
+        let _ = events().send(event);
+    }
+}
+
+// prints whatever is typed, one of the subscribers of 'run'
+// shift+page up/down scroll the screen by half of it (like the Linux console), typing scrolls back down
+pub async fn print_keypress() {
+    let mut events = subscribe();
+    while let Some(event) = events.next().await {
+        if event.state == KeyState::Pressed && event.modifiers.shift() {
+            match event.code {
+                KeyCode::PageUp => {
+                    vga_buffer::scroll_up(vga_buffer::BUFFER_HEIGHT / 2);
+                    continue;
+                }
+                KeyCode::PageDown => {
+                    vga_buffer::scroll_down(vga_buffer::BUFFER_HEIGHT / 2);
+                    continue;
                 }
+                _ => {}
             }
         }
+        if let Some(DecodedKey::Unicode(_)) = event.key { // modifiers alone don't count
+            vga_buffer::scroll_to_bottom();
+        }
+        match event.key {
+            Some(DecodedKey::Unicode(character)) => print!("{}", character),
+            Some(DecodedKey::RawKey(key)) => print!("{:?}", key),
+            None => {}
+        }
+    }
+}
+
+// IRQ1 handler, registered in 'init'
+pub(crate) fn keyboard_interrupt() {
+    let scancode = ps2::read_irq_data(); // data port of the keyboard controller (PS/2)
+    if ps2::is_keyboard_reply(scancode) { // acknowledgements of LED commands aren't keys
+        ps2::keyboard_reply(scancode);
+    } else {
+        add_scancode(scancode);
     }
 }
 
 pub(crate) fn add_scancode(scancode: u8) {
     if let Ok(queue) = SCANCODE_QUEUE.try_get() { // queue might not be initialized yet, we shouldnt init it here tho
         if queue.push(scancode).is_err() {
-            println!("WARNING: scancode queue full; dropping keyboard input");
+            log::warn!("scancode queue full; dropping keyboard input");
         } else {
             WAKER.wake();
         }
     } else {
-        println!("WARNING: scancode queue uninitialized");
+        log::warn!("scancode queue uninitialized");
     }
 }
 
@@ -78,4 +313,4 @@ impl Stream for ScancodeStream {
             None => Poll::Pending,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,87 @@
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers};
+
+// layouts selectable at runtime, all of them read scancodes of the same physical keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Us,
+    Uk,
+    German,
+    French, // AZERTY
+    Dvorak,
+    DvorakProgrammer,
+    Colemak,
+    Japanese,
+    PolishProgrammers,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 9] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::German,
+        Layout::French,
+        Layout::Dvorak,
+        Layout::DvorakProgrammer,
+        Layout::Colemak,
+        Layout::Japanese,
+        Layout::PolishProgrammers,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::German => "de",
+            Layout::French => "fr",
+            Layout::Dvorak => "dvorak",
+            Layout::DvorakProgrammer => "dvp",
+            Layout::Colemak => "colemak",
+            Layout::Japanese => "jp",
+            Layout::PolishProgrammers => "pl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+}
+
+impl KeyboardLayout for Layout {
+    fn map_keycode(&self, keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+        match self {
+            Layout::Us => layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Uk => layouts::Uk105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::German => layouts::De105Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::French => layouts::Azerty.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak => layouts::Dvorak104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::DvorakProgrammer => layouts::DVP104Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Colemak => layouts::Colemak.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Japanese => layouts::Jis109Key.map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::PolishProgrammers => polish_programmers(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+// US layout, right Alt adds the Polish letters (and the euro sign)
+fn polish_programmers(keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+    if modifiers.alt_gr {
+        let letters = match keycode {
+            KeyCode::A => Some(('ą', 'Ą')),
+            KeyCode::C => Some(('ć', 'Ć')),
+            KeyCode::E => Some(('ę', 'Ę')),
+            KeyCode::L => Some(('ł', 'Ł')),
+            KeyCode::N => Some(('ń', 'Ń')),
+            KeyCode::O => Some(('ó', 'Ó')),
+            KeyCode::S => Some(('ś', 'Ś')),
+            KeyCode::X => Some(('ź', 'Ź')),
+            KeyCode::Z => Some(('ż', 'Ż')),
+            KeyCode::U => Some(('€', '€')),
+            _ => None,
+        };
+        if let Some((lower, upper)) = letters {
+            return DecodedKey::Unicode(if modifiers.is_caps() { upper } else { lower });
+        }
+    }
+    layouts::Us104Key.map_keycode(keycode, modifiers, handle_ctrl)
+}
@@ -1,11 +1,19 @@
 use core::{future::Future, pin::Pin};
-use core::task::{Context, Poll};
-use alloc::boxed::Box;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use alloc::{boxed::Box, string::String, sync::Arc};
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use join::{JoinHandle, JoinState, Joinable};
+use crate::time::{self, Instant};
 
 pub mod executor;
+pub mod join;
 pub mod keyboard;
+pub mod mouse;
 pub mod simple_executor;
+pub mod sync;
+pub mod timer;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
@@ -18,21 +26,162 @@ impl TaskId {
     }
 }
 
+// ready tasks of higher priority are polled first, lower levels are still served every few rounds (aging)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const COUNT: usize = 3;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// future producing 'T' packaged for spawning, spawning it gives back a JoinHandle<T> for the output
+// it has to be Send, because any core's worker may poll it
+pub struct Task<T = ()> {
+    raw: RawTask,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    pub fn new(future: impl Future<Output = T> + Send + 'static) -> Self {
+        let state = JoinState::new();
+        let raw: RawTask = RawTask::new(Box::pin(Joinable::new(future, state.clone())));
+
+        Task { raw, state }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.raw.header_mut().priority = priority;
+        self
+    }
+
+    // shows up in executor snapshots, unnamed tasks are listed by id only
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.raw.header_mut().name = Some(name.into());
+        self
+    }
+
+    // splits into the part executors run and the handle returned to whoever spawned it
+    fn into_parts(self) -> (RawTask, JoinHandle<T>) {
+        (self.raw, JoinHandle::new(self.state))
+    }
+}
+
+// like Task, but the future doesn't have to be Send, it stays on the executor it was spawned on ('spawn_local')
+pub struct LocalTask<T = ()> {
+    raw: LocalRawTask,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T: 'static> LocalTask<T> {
+    pub fn new(future: impl Future<Output = T> + 'static) -> Self {
+        let state = JoinState::new();
+        let raw: LocalRawTask = RawTask::new(Box::pin(Joinable::new(future, state.clone())));
+
+        LocalTask { raw, state }
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.raw.header_mut().priority = priority;
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.raw.header_mut().name = Some(name.into());
+        self
+    }
+
+    fn into_parts(self) -> (LocalRawTask, JoinHandle<T>) {
+        (self.raw, JoinHandle::new(self.state))
+    }
+}
+
 // type wrapper around pinned, heap-allocated and dynamically dispatched future with empty type as output
-pub struct Task {
+// output of the original future is already routed to its JoinHandle, so executors only deal with this
+struct RawTask<F: ?Sized = dyn Future<Output = ()> + Send> {
     id: TaskId,
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    header: Arc<TaskHeader>, // shared with wakers and executor snapshots
+    waker: Option<Waker>, // created on first poll, moves with the task between workers
+    future: Pin<Box<F>>,
+}
+
+type LocalRawTask = RawTask<dyn Future<Output = ()>>;
+
+// everything about a task except its future, counters are atomics because wakers update them from interrupts
+struct TaskHeader {
+    name: Option<String>,
+    priority: Priority,
+    queued: AtomicBool, // set while the id sits in a wake queue, so repeated wakeups queue it only once
+    spawned_at: Instant,
+    polls: AtomicU64,
+    poll_cycles: AtomicU64, // TSC cycles spent inside poll
+    last_woken: AtomicU64, // nanos since boot, 0 = never
+    last_polled: AtomicU64,
+    owner: AtomicUsize, // worker that polled it last, wakers queue it there
 }
 
-impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
-        Task {
+impl<F: ?Sized + Future<Output = ()>> RawTask<F> {
+    fn new(future: Pin<Box<F>>) -> Self {
+        RawTask {
             id: TaskId::new(),
-            future: Box::pin(future)
+            header: Arc::new(TaskHeader {
+                name: None,
+                priority: Priority::default(),
+                queued: AtomicBool::new(false),
+                spawned_at: Instant::now(),
+                polls: AtomicU64::new(0),
+                poll_cycles: AtomicU64::new(0),
+                last_woken: AtomicU64::new(0),
+                last_polled: AtomicU64::new(0),
+                owner: AtomicUsize::new(0),
+            }),
+            waker: None,
+            future,
         }
     }
 
-    fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+    fn priority(&self) -> Priority {
+        self.header.priority
+    }
+
+    // only before spawning, nothing else holds the header yet
+    fn header_mut(&mut self) -> &mut TaskHeader {
+        Arc::get_mut(&mut self.header).expect("task header shared before spawn")
     }
-}
\ No newline at end of file
+
+    // 'make_waker' is only called on the first poll, the waker is kept for later ones
+    fn poll(&mut self, make_waker: impl FnOnce(&Arc<TaskHeader>) -> Waker) -> Poll<()> {
+        let waker = self.waker.get_or_insert_with(|| make_waker(&self.header));
+        let mut context = Context::from_waker(waker);
+
+        let start = time::rdtsc();
+        let result = self.future.as_mut().poll(&mut context);
+
+        let header = &self.header;
+        header.poll_cycles.fetch_add(time::rdtsc() - start, Ordering::Relaxed);
+        header.polls.fetch_add(1, Ordering::Relaxed);
+        header.last_polled.store(Instant::now().as_nanos(), Ordering::Relaxed);
+        result
+    }
+}
+
+// gives other ready tasks a chance to run, task is woken again right away
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|context| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        context.waker().wake_by_ref();
+        Poll::Pending
+    }).await
+}
@@ -0,0 +1,208 @@
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use core::{pin::Pin, task::{Poll, Context}};
+use futures_util::stream::Stream;
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::interrupts::{register_irq, IrqError};
+use crate::ps2::{self, Ps2Error};
+
+const MOUSE_IRQ: u8 = 12;
+const QUEUE_CAPACITY: usize = 128;
+
+// mouse commands
+const SET_DEFAULTS: u8 = 0xf6;
+const ENABLE_REPORTING: u8 = 0xf4;
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_ID: u8 = 0xf2;
+
+// packet byte 0
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3; // lets the decoder find the start of a packet
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+// filled by IRQ12, allocated by 'MouseStream::new'
+static EVENT_QUEUE: OnceCell<ArrayQueue<MouseEvent>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+// only touched by IRQ12 and 'init' (with interrupts disabled)
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new(MouseKind::Standard));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    Standard, // 3 byte packets
+    Wheel, // IntelliMouse, 4th byte is the wheel
+}
+
+impl MouseKind {
+    fn packet_size(self) -> usize {
+        match self {
+            MouseKind::Standard => 3,
+            MouseKind::Wheel => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseEvent {
+    pub dx: i16, // positive is right
+    pub dy: i16, // positive is up, like the mouse reports it
+    pub wheel: i8, // positive scrolls down
+    pub buttons: MouseButtons, // held down after this event
+}
+
+// assembles packets byte by byte
+pub struct PacketDecoder {
+    kind: MouseKind,
+    packet: [u8; 4],
+    len: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new(kind: MouseKind) -> PacketDecoder {
+        PacketDecoder { kind, packet: [0; 4], len: 0 }
+    }
+
+    pub fn kind(&self) -> MouseKind {
+        self.kind
+    }
+
+    // None until a packet is complete, bytes not starting a valid packet are dropped to resynchronize
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.kind.packet_size() {
+            return None;
+        }
+        self.len = 0;
+
+        let flags = self.packet[0];
+        // movement is 9 bit two's complement, sign bit is in the first byte
+        let delta = |value: u8, sign: u8, overflow: u8| match flags & overflow != 0 {
+            true => 0, // value is garbage when the counter overflowed
+            false => value as i16 - if flags & sign != 0 { 0x100 } else { 0 },
+        };
+        Some(MouseEvent {
+            dx: delta(self.packet[1], X_SIGN, X_OVERFLOW),
+            dy: delta(self.packet[2], Y_SIGN, Y_OVERFLOW),
+            wheel: match self.kind {
+                MouseKind::Standard => 0,
+                MouseKind::Wheel => self.packet[3] as i8,
+            },
+            buttons: MouseButtons {
+                left: flags & LEFT != 0,
+                right: flags & RIGHT != 0,
+                middle: flags & MIDDLE != 0,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseError {
+    Controller(Ps2Error),
+    Irq(IrqError),
+}
+
+impl From<Ps2Error> for MouseError {
+    fn from(err: Ps2Error) -> Self {
+        MouseError::Controller(err)
+    }
+}
+
+// sets up the auxiliary PS/2 port and the mouse behind it, then starts handling IRQ12
+// wheel mice are switched into 4 byte mode by the IntelliMouse sample rate sequence
+pub fn init() -> Result<MouseKind, MouseError> {
+    let kind = interrupts::without_interrupts(|| -> Result<MouseKind, Ps2Error> {
+        ps2::enable_aux()?;
+        ps2::aux_command(SET_DEFAULTS)?;
+        for rate in [200, 100, 80] {
+            ps2::aux_command(SET_SAMPLE_RATE)?;
+            ps2::aux_command(rate)?;
+        }
+        ps2::aux_command(GET_ID)?;
+        let kind = match ps2::read_aux()? {
+            3 => MouseKind::Wheel,
+            _ => MouseKind::Standard,
+        };
+        ps2::aux_command(ENABLE_REPORTING)?;
+        *DECODER.lock() = PacketDecoder::new(kind);
+        Ok(kind)
+    })?;
+
+    register_irq(MOUSE_IRQ, mouse_interrupt).map_err(MouseError::Irq)?;
+    Ok(kind)
+}
+
+fn mouse_interrupt() {
+    let byte = ps2::read_irq_data();
+    if let Some(event) = DECODER.lock().add_byte(byte) {
+        add_event(event);
+    }
+}
+
+fn add_event(event: MouseEvent) {
+    // nobody listening yet, a mouse moving without a stream is nothing to warn about
+    if let Ok(queue) = EVENT_QUEUE.try_get() {
+        queue.force_push(event); // slow reader loses the oldest movement instead of the newest
+        WAKER.wake();
+    }
+}
+
+pub struct MouseStream {
+    _private: (), // to prevent construction from outside of the module
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        EVENT_QUEUE.try_init_once(|| ArrayQueue::new(QUEUE_CAPACITY))
+            .expect("MouseStream::new should only be called once");
+        MouseStream { _private: () }
+    }
+}
+
+impl Default for MouseStream {
+    fn default() -> Self {
+        MouseStream::new()
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queue = EVENT_QUEUE
+            .try_get()
+            .expect("not initialized");
+
+        // fast path
+        if let Some(event) = queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(event) => {
+                WAKER.take(); // remove registered waker, notification no longer needed
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
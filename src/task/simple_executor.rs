@@ -1,9 +1,9 @@
-use super::Task;
+use super::{join::JoinHandle, RawTask, Task};
 use alloc::collections::VecDeque;
-use core::task::{RawWaker, RawWakerVTable, Waker, Context, Poll};
+use core::task::{RawWaker, RawWakerVTable, Waker, Poll};
 
 pub struct SimpleExecutor {
-    task_queue: VecDeque<Task>, // simple FIFO queue
+    task_queue: VecDeque<RawTask>, // simple FIFO queue
 }
 
 impl SimpleExecutor {
@@ -13,15 +13,15 @@ impl SimpleExecutor {
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
+    pub fn spawn<T: Send + 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (task, handle) = task.into_parts();
         self.task_queue.push_back(task);
+        handle
     }
 
     pub fn run(&mut self) {
         while let Some(mut task) = self.task_queue.pop_front() {
-            let waker = dummy_waker();
-            let mut context = Context::from_waker(&waker);
-            match task.poll(&mut context) {
+            match task.poll(|_| dummy_waker()) {
                 Poll::Ready(()) => {} // task done
                 Poll::Pending => self.task_queue.push_back(task), // add it to the back
             }
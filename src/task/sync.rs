@@ -0,0 +1,85 @@
+use alloc::collections::VecDeque;
+use core::task::Waker;
+use x86_64::instructions::interrupts;
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod mutex;
+pub mod notify;
+pub mod oneshot;
+pub mod rwlock;
+pub mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::Notify;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
+// every primitive keeps its state behind a spin lock taken with interrupts disabled,
+// so an interrupt handler signalling it can't spin on a lock held by the task it interrupted
+fn locked<T, R>(lock: &spin::Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut lock.lock()))
+}
+
+// FIFO of tasks waiting for a primitive, each waiting future owns a key so it can update or remove its entry
+// waking never allocates (it only pops), so it's fine in interrupt handlers
+struct WaitList {
+    waiters: VecDeque<(u64, Waker)>,
+    next_key: u64,
+}
+
+impl WaitList {
+    const fn new() -> Self {
+        WaitList { waiters: VecDeque::new(), next_key: 0 }
+    }
+
+    // adds the waiter, or only refreshes its waker when it's still queued
+    fn register(&mut self, key: &mut Option<u64>, waker: &Waker) {
+        if let Some(entry) = key.and_then(|key| self.waiters.iter_mut().find(|(k, _)| *k == key)) {
+            entry.1.clone_from(waker);
+            return;
+        }
+
+        let new_key = self.next_key;
+        self.next_key += 1;
+        self.waiters.push_back((new_key, waker.clone()));
+        *key = Some(new_key);
+    }
+
+    // false if the waiter was already woken (and so removed)
+    fn remove(&mut self, key: u64) -> bool {
+        match self.waiters.iter().position(|(k, _)| *k == key) {
+            Some(index) => {
+                self.waiters.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn contains(&self, key: u64) -> bool {
+        self.waiters.iter().any(|(k, _)| *k == key)
+    }
+
+    fn wake_one(&mut self) -> bool {
+        match self.waiters.pop_front() {
+            Some((_, waker)) => {
+                waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn wake_all(&mut self) {
+        while self.wake_one() {}
+    }
+}
+
+// shared Drop logic of waiting futures: a waiter that was woken but is dropped before acting on it
+// passes the wakeup on, otherwise it would be lost and the next waiter could wait forever
+fn cancel_wait(waiters: &mut WaitList, key: Option<u64>) {
+    if let Some(key) = key && !waiters.remove(key) {
+        waiters.wake_one();
+    }
+}
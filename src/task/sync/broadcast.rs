@@ -0,0 +1,201 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{locked, WaitList};
+
+// every receiver sees every value sent after it subscribed, as long as it keeps up:
+// buffer keeps the last 'capacity' values and receivers falling further behind get Lagged
+// 'send' doesn't allocate, but it drops the oldest value, so from interrupt handlers send plain data only
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel needs capacity");
+    let shared = Arc::new(spin::Mutex::new(State {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        first_seq: 0,
+        senders: 1,
+        receivers: 1,
+        waiters: WaitList::new(),
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared, next: 0 })
+}
+
+struct State<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    first_seq: u64, // sequence number of buffer[0]
+    senders: usize,
+    receivers: usize,
+    waiters: WaitList,
+}
+
+impl<T> State<T> {
+    fn end_seq(&self) -> u64 {
+        self.first_seq + self.buffer.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T); // no receivers
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    Lagged(u64), // that many values were overwritten before this receiver got to them
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Lagged(u64),
+    Closed,
+}
+
+type Shared<T> = Arc<spin::Mutex<State<T>>>;
+
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        locked(&self.shared, |state| {
+            if state.receivers == 0 {
+                return Err(SendError(value));
+            }
+            if state.buffer.len() == state.capacity {
+                state.buffer.pop_front();
+                state.first_seq += 1;
+            }
+            state.buffer.push_back(value); // capacity was reserved up front
+            state.waiters.wake_all();
+            Ok(())
+        })
+    }
+
+    // new receiver starts with values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        locked(&self.shared, |state| {
+            state.receivers += 1;
+            Receiver { shared: self.shared.clone(), next: state.end_seq() }
+        })
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        locked(&self.shared, |state| state.receivers)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        locked(&self.shared, |state| state.senders += 1);
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        locked(&self.shared, |state| {
+            state.senders -= 1;
+            if state.senders == 0 {
+                state.waiters.wake_all();
+            }
+        });
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Shared<T>,
+    next: u64, // sequence number of the next value to receive
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        locked(&self.shared, |state| {
+            if self.next < state.first_seq {
+                let lagged = state.first_seq - self.next;
+                self.next = state.first_seq;
+                return Err(TryRecvError::Lagged(lagged));
+            }
+            if self.next < state.end_seq() {
+                let value = state.buffer[(self.next - state.first_seq) as usize].clone();
+                self.next += 1;
+                return Ok(value);
+            }
+            match state.senders {
+                0 => Err(TryRecvError::Closed),
+                _ => Err(TryRecvError::Empty),
+            }
+        })
+    }
+
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self, key: None }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    // the clone continues from the same position
+    fn clone(&self) -> Self {
+        locked(&self.shared, |state| state.receivers += 1);
+        Receiver { shared: self.shared.clone(), next: self.next }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        locked(&self.shared, |state| state.receivers -= 1);
+    }
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    key: Option<u64>,
+}
+
+impl<T: Clone> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let result = match this.receiver.try_recv() {
+            Ok(value) => Ok(value),
+            Err(TryRecvError::Lagged(lagged)) => Err(RecvError::Lagged(lagged)),
+            Err(TryRecvError::Closed) => Err(RecvError::Closed),
+            Err(TryRecvError::Empty) => {
+                // check again with the waiter registered, send in between would be missed otherwise
+                let receiver = &*this.receiver;
+                let key = &mut this.key;
+                let pending = locked(&receiver.shared, |state| {
+                    let pending = receiver.next >= state.end_seq() && state.senders > 0;
+                    if pending {
+                        state.waiters.register(key, context.waker());
+                    }
+                    pending
+                });
+                if pending {
+                    return Poll::Pending;
+                }
+                return Pin::new(this).poll(context);
+            }
+        };
+
+        if let Some(key) = this.key.take() {
+            locked(&this.receiver.shared, |state| state.waiters.remove(key));
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl<T> Drop for Recv<'_, T> {
+    fn drop(&mut self) {
+        // every waiter is woken on send, so there's nothing to pass on, only the entry to remove
+        let key = self.key;
+        locked(&self.receiver.shared, |state| {
+            if let Some(key) = key {
+                state.waiters.remove(key);
+            }
+        });
+    }
+}
@@ -0,0 +1,196 @@
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::task::AtomicWaker;
+
+use super::{cancel_wait, locked, WaitList};
+
+// bounded multi-producer single-consumer queue
+// 'try_send' is lock-free and never allocates, so interrupt handlers can feed tasks through it
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel needs capacity");
+    let shared = Arc::new(Shared {
+        buffer: ArrayQueue::new(capacity),
+        receiver_waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        space: spin::Mutex::new(WaitList::new()),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+struct Shared<T> {
+    buffer: ArrayQueue<T>,
+    receiver_waker: AtomicWaker,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    space: spin::Mutex<WaitList>, // senders waiting for a free slot
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T); // receiver dropped
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed, // empty and every sender dropped
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Acquire) {
+            return Err(TrySendError::Closed(value));
+        }
+        self.shared.buffer.push(value).map_err(TrySendError::Full)?;
+        self.shared.receiver_waker.wake();
+        Ok(())
+    }
+
+    // waits for a free slot when the channel is full
+    pub fn send(&self, value: T) -> Send<'_, T> {
+        Send { sender: self, value: Some(value), key: None }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.shared.receiver_alive.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.receiver_waker.wake(); // last one, receiver sees the channel closed
+        }
+    }
+}
+
+pub struct Send<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    key: Option<u64>,
+}
+
+impl<T> Unpin for Send<'_, T> {} // value is only ever moved out, never pinned
+
+impl<T> Future for Send<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let value = this.value.take().expect("Send polled after completion");
+        let shared = &this.sender.shared;
+
+        let result = match this.sender.try_send(value) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(value)) => Err(SendError(value)),
+            Err(TrySendError::Full(value)) => {
+                // registered before trying again, a slot freed in between wakes us instead of being missed
+                let retry = locked(&shared.space, |waiters| {
+                    waiters.register(&mut this.key, context.waker());
+                    shared.buffer.push(value)
+                });
+                match retry {
+                    Ok(()) => {
+                        shared.receiver_waker.wake();
+                        Ok(())
+                    }
+                    Err(value) => {
+                        this.value = Some(value);
+                        return Poll::Pending;
+                    }
+                }
+            }
+        };
+
+        if let Some(key) = this.key.take() {
+            locked(&shared.space, |waiters| waiters.remove(key));
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl<T> Drop for Send<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.sender.shared.space, |waiters| cancel_wait(waiters, self.key));
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.pop() {
+            return Ok(value);
+        }
+        if self.shared.senders.load(Ordering::Acquire) != 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // the last sender might have pushed right before it dropped, after the pop above
+        self.pop().ok_or(TryRecvError::Closed)
+    }
+
+    fn pop(&self) -> Option<T> {
+        let value = self.shared.buffer.pop()?;
+        locked(&self.shared.space, |waiters| waiters.wake_one());
+        Some(value)
+    }
+
+    // None once the channel is empty and every sender is gone
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        locked(&self.shared.space, |waiters| waiters.wake_all());
+    }
+}
+
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        // fast path, then register and check again, same as ScancodeStream
+        // 'try_recv' only reports Closed after one more pop, so a message sent by the last sender isn't lost
+        match self.receiver.try_recv() {
+            Ok(value) => return Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        self.receiver.shared.receiver_waker.register(context.waker());
+        match self.receiver.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{cancel_wait, locked, WaitList};
+
+// lock held across await points, waiting tasks are suspended instead of spinning
+pub struct Mutex<T: ?Sized> {
+    state: spin::Mutex<State>,
+    data: UnsafeCell<T>,
+}
+
+struct State {
+    locked: bool,
+    waiters: WaitList,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: spin::Mutex::new(State { locked: false, waiters: WaitList::new() }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> Lock<'_, T> {
+        Lock { mutex: self, key: None }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let acquired = locked(&self.state, |state| !core::mem::replace(&mut state.locked, true));
+        acquired.then_some(MutexGuard { mutex: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct Lock<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    key: Option<u64>,
+}
+
+impl<'a, T: ?Sized> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<MutexGuard<'a, T>> {
+        let this = &mut *self;
+        locked(&this.mutex.state, |state| {
+            if state.locked {
+                state.waiters.register(&mut this.key, context.waker());
+                return Poll::Pending;
+            }
+
+            state.locked = true;
+            if let Some(key) = this.key.take() {
+                state.waiters.remove(key);
+            }
+            Poll::Ready(MutexGuard { mutex: this.mutex })
+        })
+    }
+}
+
+impl<T: ?Sized> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.mutex.state, |state| cancel_wait(&mut state.waiters, self.key));
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.mutex.state, |state| {
+            state.locked = false;
+            state.waiters.wake_one();
+        });
+    }
+}
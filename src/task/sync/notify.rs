@@ -0,0 +1,96 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{locked, WaitList};
+
+// wakes waiting tasks without passing any data, meant for interrupt handlers telling a task that something happened
+// 'notify_one' with nobody waiting stores a single permit, so the next 'notified' completes right away
+pub struct Notify {
+    state: spin::Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    generation: u64, // bumped by 'notify_waiters', lets woken futures tell it apart from a spurious poll
+    waiters: WaitList,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify { state: spin::Mutex::new(State { permit: false, generation: 0, waiters: WaitList::new() }) }
+    }
+
+    pub fn notify_one(&self) {
+        locked(&self.state, |state| {
+            if !state.waiters.wake_one() {
+                state.permit = true;
+            }
+        });
+    }
+
+    // wakes everyone waiting right now, doesn't store a permit
+    pub fn notify_waiters(&self) {
+        locked(&self.state, |state| {
+            state.generation += 1;
+            state.waiters.wake_all();
+        });
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified { notify: self, key: None, generation: None }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    key: Option<u64>,
+    generation: Option<u64>, // generation at first poll
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        locked(&this.notify.state, |state| {
+            let woken = this.key.is_some_and(|key| !state.waiters.contains(key)); // notify_one took it off the list
+            let broadcast = this.generation.is_some_and(|generation| generation != state.generation);
+
+            if !woken && !broadcast {
+                if !state.permit {
+                    this.generation.get_or_insert(state.generation);
+                    state.waiters.register(&mut this.key, context.waker());
+                    return Poll::Pending;
+                }
+                state.permit = false;
+            }
+
+            if let Some(key) = this.key.take() {
+                state.waiters.remove(key);
+            }
+            Poll::Ready(())
+        })
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        locked(&self.notify.state, |state| {
+            let Some(key) = self.key else { return };
+            let broadcast = self.generation.is_some_and(|generation| generation != state.generation);
+            if !state.waiters.remove(key) && !broadcast {
+                // got a notify_one but wasn't polled again, hand it over
+                if !state.waiters.wake_one() {
+                    state.permit = true;
+                }
+            }
+        });
+    }
+}
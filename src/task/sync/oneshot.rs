@@ -0,0 +1,101 @@
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use super::locked;
+
+// single value from one producer to one consumer, e.g. result of a request handled by another task
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(spin::Mutex::new(State {
+        value: None,
+        sender_alive: true,
+        receiver_alive: true,
+        waker: None,
+    }));
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+struct State<T> {
+    value: Option<T>,
+    sender_alive: bool,
+    receiver_alive: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError; // sender dropped without sending
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+pub struct Sender<T> {
+    inner: Arc<spin::Mutex<State<T>>>,
+}
+
+impl<T> Sender<T> {
+    // gives the value back if the receiver is gone
+    pub fn send(self, value: T) -> Result<(), T> {
+        locked(&self.inner, |state| {
+            if !state.receiver_alive {
+                return Err(value);
+            }
+            state.value = Some(value);
+            Ok(())
+        })
+        // waking happens in drop
+    }
+
+    pub fn is_closed(&self) -> bool {
+        locked(&self.inner, |state| !state.receiver_alive)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        locked(&self.inner, |state| {
+            state.sender_alive = false;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<spin::Mutex<State<T>>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        locked(&self.inner, |state| match state.value.take() {
+            Some(value) => Ok(value),
+            None if state.sender_alive => Err(TryRecvError::Empty),
+            None => Err(TryRecvError::Closed),
+        })
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        locked(&self.inner, |state| match state.value.take() {
+            Some(value) => Poll::Ready(Ok(value)),
+            None if !state.sender_alive => Poll::Ready(Err(RecvError)),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        locked(&self.inner, |state| state.receiver_alive = false);
+    }
+}
@@ -0,0 +1,205 @@
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{cancel_wait, locked, WaitList};
+
+// many readers or one writer, new readers queue up behind a waiting writer so writers don't starve
+pub struct RwLock<T: ?Sized> {
+    state: spin::Mutex<State>,
+    data: UnsafeCell<T>,
+}
+
+struct State {
+    readers: usize,
+    writer: bool,
+    writers_waiting: usize,
+    waiters: WaitList,
+}
+
+impl State {
+    fn can_read(&self) -> bool {
+        !self.writer && self.writers_waiting == 0
+    }
+
+    fn can_write(&self) -> bool {
+        !self.writer && self.readers == 0
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: spin::Mutex::new(State { readers: 0, writer: false, writers_waiting: 0, waiters: WaitList::new() }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> Read<'_, T> {
+        Read { lock: self, key: None }
+    }
+
+    pub fn write(&self) -> Write<'_, T> {
+        Write { lock: self, key: None, counted: false }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let acquired = locked(&self.state, |state| {
+            let can_read = state.can_read();
+            state.readers += can_read as usize;
+            can_read
+        });
+        acquired.then_some(RwLockReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let acquired = locked(&self.state, |state| {
+            let can_write = state.can_write();
+            state.writer |= can_write;
+            can_write
+        });
+        acquired.then_some(RwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct Read<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    key: Option<u64>,
+}
+
+impl<'a, T: ?Sized> Future for Read<'a, T> {
+    type Output = RwLockReadGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<RwLockReadGuard<'a, T>> {
+        let this = &mut *self;
+        locked(&this.lock.state, |state| {
+            if !state.can_read() {
+                state.waiters.register(&mut this.key, context.waker());
+                return Poll::Pending;
+            }
+
+            state.readers += 1;
+            if let Some(key) = this.key.take() {
+                state.waiters.remove(key);
+            }
+            Poll::Ready(RwLockReadGuard { lock: this.lock })
+        })
+    }
+}
+
+impl<T: ?Sized> Drop for Read<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.lock.state, |state| cancel_wait(&mut state.waiters, self.key));
+    }
+}
+
+pub struct Write<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    key: Option<u64>,
+    counted: bool, // included in writers_waiting
+}
+
+impl<'a, T: ?Sized> Future for Write<'a, T> {
+    type Output = RwLockWriteGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<RwLockWriteGuard<'a, T>> {
+        let this = &mut *self;
+        locked(&this.lock.state, |state| {
+            if !state.can_write() {
+                if !this.counted {
+                    state.writers_waiting += 1;
+                    this.counted = true;
+                }
+                state.waiters.register(&mut this.key, context.waker());
+                return Poll::Pending;
+            }
+
+            state.writer = true;
+            if this.counted {
+                state.writers_waiting -= 1;
+                this.counted = false;
+            }
+            if let Some(key) = this.key.take() {
+                state.waiters.remove(key);
+            }
+            Poll::Ready(RwLockWriteGuard { lock: this.lock })
+        })
+    }
+}
+
+impl<T: ?Sized> Drop for Write<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.lock.state, |state| {
+            if self.counted {
+                state.writers_waiting -= 1;
+                state.waiters.wake_all(); // readers held back by this writer can go now
+            }
+            cancel_wait(&mut state.waiters, self.key);
+        });
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.lock.state, |state| {
+            state.readers -= 1;
+            if state.readers == 0 {
+                state.waiters.wake_all();
+            }
+        });
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        locked(&self.lock.state, |state| {
+            state.writer = false;
+            state.waiters.wake_all(); // waiting readers and writers race for it, losers register again
+        });
+    }
+}
@@ -0,0 +1,100 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{cancel_wait, locked, WaitList};
+
+// counts available permits, 'add_permits' doesn't allocate so interrupt handlers can use it to signal tasks
+pub struct Semaphore {
+    state: spin::Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    waiters: WaitList,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore { state: spin::Mutex::new(State { permits, waiters: WaitList::new() }) }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        locked(&self.state, |state| state.permits)
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        locked(&self.state, |state| {
+            state.permits += permits;
+            for _ in 0..permits {
+                if !state.waiters.wake_one() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // permit is given back when the returned guard is dropped
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire { semaphore: self, key: None }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let acquired = locked(&self.state, |state| match state.permits {
+            0 => false,
+            _ => {
+                state.permits -= 1;
+                true
+            }
+        });
+        acquired.then_some(SemaphorePermit { semaphore: self })
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    key: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let this = &mut *self;
+        locked(&this.semaphore.state, |state| {
+            if state.permits == 0 {
+                state.waiters.register(&mut this.key, context.waker());
+                return Poll::Pending;
+            }
+
+            state.permits -= 1;
+            if let Some(key) = this.key.take() {
+                state.waiters.remove(key);
+            }
+            Poll::Ready(SemaphorePermit { semaphore: this.semaphore })
+        })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        locked(&self.semaphore.state, |state| cancel_wait(&mut state.waiters, self.key));
+    }
+}
+
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    // consumes the permit for good, e.g. when it stands for an item that was taken
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(1);
+    }
+}
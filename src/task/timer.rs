@@ -0,0 +1,245 @@
+use alloc::{collections::BinaryHeap, sync::Arc, vec::Vec};
+use core::cmp::Ordering as CmpOrdering;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use futures_util::{stream::Stream, task::AtomicWaker};
+use spin::Mutex;
+
+use crate::time::Instant;
+
+// pending deadlines, earliest on top
+// wakers are woken by the executor loop and not straight from the timer interrupt: waking drops a Waker,
+// which can free memory, and the interrupted code might be holding the heap lock
+static TIMERS: Mutex<Timers> = Mutex::new(Timers { heap: BinaryHeap::new(), cancelled: 0 });
+// earliest deadline in nanos since boot, lets the executor skip locking when nothing expired
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+struct Timers {
+    heap: BinaryHeap<TimerRef>,
+    cancelled: usize, // entries in the heap whose Sleep was dropped, purged once they outnumber the live ones
+}
+
+impl Timers {
+    fn purge_cancelled(&mut self) {
+        self.heap.retain(|timer| !timer.0.cancelled.load(Ordering::Relaxed));
+        self.cancelled = 0;
+        update_next_deadline(&self.heap);
+    }
+}
+
+// flags are only changed with TIMERS locked
+struct TimerEntry {
+    deadline: Instant,
+    waker: AtomicWaker,
+    queued: AtomicBool, // still in the heap
+    cancelled: AtomicBool,
+}
+
+struct TimerRef(Arc<TimerEntry>);
+
+// BinaryHeap is a max-heap, so ordering is reversed to keep the earliest deadline on top
+impl Ord for TimerRef {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other.0.deadline.cmp(&self.0.deadline)
+    }
+}
+
+impl PartialOrd for TimerRef {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TimerRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.deadline == other.0.deadline
+    }
+}
+
+impl Eq for TimerRef {}
+
+// true when some deadline passed and 'wake_expired' has work to do, checked before halting
+pub fn has_expired() -> bool {
+    Instant::now().as_nanos() >= NEXT_DEADLINE.load(Ordering::Acquire)
+}
+
+// wakes every task whose deadline passed, called by the executor after each timer tick woke it up
+pub fn wake_expired() {
+    if !has_expired() {
+        return;
+    }
+
+    let now = Instant::now();
+    let mut expired = Vec::new();
+    {
+        let mut timers = TIMERS.lock();
+        while timers.heap.peek().is_some_and(|timer| timer.0.deadline <= now) {
+            let timer = timers.heap.pop().expect("peeked above");
+            timer.0.queued.store(false, Ordering::Relaxed);
+            if timer.0.cancelled.load(Ordering::Relaxed) {
+                timers.cancelled -= 1;
+            } else {
+                expired.push(timer);
+            }
+        }
+        update_next_deadline(&timers.heap);
+    }
+    // woken without the lock held, so a woken future can register a new timer right away
+    for timer in expired {
+        timer.0.waker.wake();
+    }
+}
+
+fn register(entry: Arc<TimerEntry>) {
+    let mut timers = TIMERS.lock();
+    entry.queued.store(true, Ordering::Relaxed);
+    timers.heap.push(TimerRef(entry));
+    update_next_deadline(&timers.heap);
+}
+
+// deadlines still waited for, including cancelled ones that weren't purged yet
+pub fn pending_timers() -> usize {
+    TIMERS.lock().heap.len()
+}
+
+fn update_next_deadline(timers: &BinaryHeap<TimerRef>) {
+    let next = timers.peek().map_or(u64::MAX, |timer| timer.0.deadline.as_nanos());
+    NEXT_DEADLINE.store(next, Ordering::Release);
+}
+
+// future completing once 'deadline' passes
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<Arc<TimerEntry>>, // registered on first pending poll
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, entry: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        match &self.entry {
+            Some(entry) => entry.waker.register(context.waker()), // polled again, possibly by a different task
+            None => {
+                let entry = Arc::new(TimerEntry {
+                    deadline: self.deadline,
+                    waker: AtomicWaker::new(),
+                    queued: AtomicBool::new(false),
+                    cancelled: AtomicBool::new(false),
+                });
+                entry.waker.register(context.waker());
+                register(entry.clone());
+                self.entry = Some(entry);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // entry stays in the heap until its deadline or the next purge, but it won't wake anyone anymore
+        if let Some(entry) = &self.entry {
+            entry.waker.take();
+            let mut timers = TIMERS.lock();
+            entry.cancelled.store(true, Ordering::Relaxed);
+            if entry.queued.load(Ordering::Relaxed) {
+                timers.cancelled += 1;
+                if timers.cancelled * 2 > timers.heap.len() {
+                    timers.purge_cancelled();
+                }
+            }
+        }
+    }
+}
+
+// yields every 'period', missed ticks are skipped instead of firing in a burst
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval { period, sleep: sleep(period) }
+}
+
+impl Interval {
+    pub async fn tick(&mut self) -> Instant {
+        futures_util::StreamExt::next(self).await.expect("interval never ends")
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Instant>> {
+        let deadline = self.sleep.deadline();
+        if Pin::new(&mut self.sleep).poll(context).is_pending() {
+            return Poll::Pending;
+        }
+
+        let now = Instant::now();
+        let mut next = deadline + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep = sleep_until(next);
+        Poll::Ready(Some(deadline))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+// resolves to the output of 'future', or Err(Elapsed) if it didn't finish within 'duration'
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout { future, sleep: sleep(duration) }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        // 'future' is never moved out of the pinned Timeout, 'sleep' is Unpin
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if let Poll::Ready(output) = future.poll(context) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(context) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
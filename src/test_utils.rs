@@ -1,5 +1,7 @@
-use crate::{halt, serial_print, serial_println};
+use crate::{halt, output, serial_print, serial_println};
+use crate::interrupts::exceptions::{self, Exception, ExceptionReport};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 
 const TEST_IOBASE_PORT: u16 = 0xf4;
 
@@ -29,6 +31,7 @@ pub fn test_runner(tests: &[&dyn Testable]) { // &[&dyn Testable()] is a slice o
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    output::panic_mode();
     serial_println!("[fail]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failure);
@@ -46,8 +49,76 @@ pub enum QemuExitCode {
 pub fn exit_qemu(exit_code: QemuExitCode){
     use x86_64::instructions::port::Port;
 
+    output::flush_blocking(); // QEMU is gone right after, buffered output would be lost
     unsafe { // unsafe bcs writing to an I/O port can generally result in arbitrary behavior
         let mut port = Port::new(TEST_IOBASE_PORT); // 0xf4 is the value of iobase arg
         port.write(exit_code as u32); // u32 bcs iosize byte equals 4bytes
     }
-}
\ No newline at end of file
+}
+const ANY_ERROR_CODE: u64 = u64::MAX;
+
+static EXPECTED_EXCEPTION: AtomicU8 = AtomicU8::new(u8::MAX);
+static EXPECTED_ERROR_CODE: AtomicU64 = AtomicU64::new(ANY_ERROR_CODE);
+
+// makes the next fatal exception end the test, successfully only if it's the expected one
+pub fn expect_fatal_exception(exception: Exception) {
+    EXPECTED_EXCEPTION.store(exception.vector(), Ordering::SeqCst);
+    exceptions::set_fatal_hook(expected_exception_hook);
+}
+
+// like 'expect_fatal_exception', the CPU also has to report 'error_code' with it
+pub fn expect_fatal_error_code(exception: Exception, error_code: u64) {
+    EXPECTED_ERROR_CODE.store(error_code, Ordering::SeqCst);
+    expect_fatal_exception(exception);
+}
+
+fn expected_exception_hook(report: &ExceptionReport) -> ! {
+    let expected_code = EXPECTED_ERROR_CODE.load(Ordering::SeqCst);
+    if report.exception.vector() != EXPECTED_EXCEPTION.load(Ordering::SeqCst) {
+        serial_println!("[fail]\n");
+        serial_println!("Error: unexpected {}\n", report.exception.name());
+        exit_qemu(QemuExitCode::Failure);
+    } else if expected_code != ANY_ERROR_CODE && report.error_code != Some(expected_code) {
+        serial_println!("[fail]\n");
+        serial_println!("Error: expected error code {:#x}, got {:x?}\n", expected_code, report.error_code);
+        exit_qemu(QemuExitCode::Failure);
+    } else {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    }
+    halt()
+}
+
+// expands to a whole integration test executable for an exception that can't be recovered from
+// it boots, expects '$exception' to be reported as fatal and runs '$trigger', which should raise it
+// with 'error_code = ...' the reported error code has to match too
+#[macro_export]
+macro_rules! fatal_exception_test {
+    (@test $trigger:block, $expect:block) => {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn _start() -> ! {
+            $crate::serial_print!("{}...\t", core::module_path!());
+            $crate::init();
+            $expect
+            $trigger
+            $crate::serial_println!("[exception not raised]");
+            $crate::test_utils::exit_qemu($crate::test_utils::QemuExitCode::Failure);
+            $crate::halt()
+        }
+
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::test_utils::test_panic_handler(info)
+        }
+    };
+    ($exception:expr, error_code = $error_code:expr, $trigger:block) => {
+        $crate::fatal_exception_test!(@test $trigger, {
+            $crate::test_utils::expect_fatal_error_code($exception, $error_code);
+        });
+    };
+    ($exception:expr, $trigger:block) => {
+        $crate::fatal_exception_test!(@test $trigger, {
+            $crate::test_utils::expect_fatal_exception($exception);
+        });
+    };
+}
@@ -0,0 +1,367 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::{self, interrupts};
+
+use crate::memory::stack::{self, KernelStack};
+use crate::smp::{self, MAX_CPUS};
+use crate::time::{self, Instant};
+
+mod switch;
+
+use switch::{switch_context, thread_start, InitialFrame};
+
+const STACK_PAGES: usize = 16;
+const TIME_SLICE: Duration = Duration::from_millis(10); // a thread runs at most this long while others are ready
+
+// one round-robin scheduler per CPU, threads stay on the CPU they were spawned on
+// only ever locked with interrupts disabled, so the timer interrupt can't find it held by its own CPU
+static SCHEDULERS: [Mutex<Option<Scheduler>>; MAX_CPUS] = [const { Mutex::new(None) }; MAX_CPUS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        // each id will be assigned only once
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    Ready,
+    Sleeping(Instant),
+    Blocked, // waits for 'wake'
+    Finished,
+}
+
+struct Thread {
+    id: ThreadId,
+    state: State,
+    rsp: u64, // saved stack pointer while not running, callee-saved registers are on that stack
+    stack: Option<KernelStack>, // None for the thread that was running when 'init' was called
+}
+
+impl Thread {
+    // stack is prepared so the first switch to the thread ends up in 'thread_main(main)'
+    fn spawn(main: Box<dyn FnOnce() + Send>) -> Box<Thread> {
+        let stack = stack::allocate_stack(STACK_PAGES).expect("failed to allocate thread stack");
+        let main = Box::into_raw(Box::new(main)); // thin pointer fits into a register
+        let start: unsafe extern "sysv64" fn() -> ! = thread_start;
+        let frame = InitialFrame {
+            r15: 0,
+            r14: 0,
+            r13: 0,
+            r12: main as u64,
+            rbx: 0,
+            rbp: 0,
+            ret: start as usize as u64,
+        };
+        let rsp = stack.top().as_u64() - mem::size_of::<InitialFrame>() as u64;
+        unsafe { (rsp as *mut InitialFrame).write(frame) };
+        Box::new(Thread { id: ThreadId::new(), state: State::Ready, rsp, stack: Some(stack) })
+    }
+}
+
+struct Scheduler {
+    current: ThreadId,
+    idle: ThreadId, // runs when nothing else is ready, never in the ready queue
+    threads: BTreeMap<ThreadId, Box<Thread>>, // boxed, so saved stack pointers don't move
+    ready: VecDeque<ThreadId>,
+    sleeping: Vec<(Instant, ThreadId)>,
+    #[allow(clippy::vec_box)] // moved here from 'threads' while its stack pointer is still being saved
+    dead: Vec<Box<Thread>>, // finished threads whose stacks are freed by 'reap'
+    slice_start: Instant,
+}
+
+impl Scheduler {
+    fn wake(&mut self, id: ThreadId) {
+        let Some(thread) = self.threads.get_mut(&id) else { return };
+        if !matches!(thread.state, State::Blocked | State::Sleeping(_)) {
+            return;
+        }
+        if id == self.current {
+            thread.state = State::Running; // blocked, but didn't get to switch away yet
+        } else {
+            thread.state = State::Ready;
+            self.ready.push_back(id);
+        }
+    }
+
+    fn wake_sleepers(&mut self, now: Instant) {
+        let mut index = 0;
+        while index < self.sleeping.len() {
+            let (deadline, id) = self.sleeping[index];
+            if deadline <= now {
+                self.sleeping.swap_remove(index);
+                self.wake(id);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    // preemption only happens when the slice is used up or the idle thread holds the CPU
+    fn should_preempt(&self, now: Instant) -> bool {
+        !self.ready.is_empty() && (self.current == self.idle || now - self.slice_start >= TIME_SLICE)
+    }
+
+    // picks the next thread and updates states, returns where to save the current stack pointer and the one to load
+    // None when the current thread keeps running
+    fn switch_out(&mut self, now: Instant) -> Option<(*mut u64, u64)> {
+        self.wake_sleepers(now);
+        let current = self.current;
+        let state = self.threads[&current].state;
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if state == State::Running => return None,
+            None => self.idle,
+        };
+
+        let old = self.threads.get_mut(&current).expect("current thread is known");
+        let old_rsp = &raw mut old.rsp;
+        match state {
+            State::Running if current == self.idle => old.state = State::Ready,
+            State::Running => {
+                old.state = State::Ready;
+                self.ready.push_back(current);
+            }
+            State::Finished => {
+                let old = self.threads.remove(&current).expect("current thread is known");
+                self.dead.push(old); // box keeps its address, so 'old_rsp' stays valid
+            }
+            _ => {}
+        }
+
+        let new = self.threads.get_mut(&next).expect("ready thread is known");
+        new.state = State::Running;
+        self.current = next;
+        self.slice_start = now;
+        Some((old_rsp, new.rsp))
+    }
+}
+
+// runs 'f' on this CPU's scheduler, None before 'init' ran here
+// caller has to disable interrupts
+fn with_scheduler<R>(f: impl FnOnce(&mut Scheduler) -> R) -> Option<R> {
+    SCHEDULERS.get(smp::cpu_id())?.lock().as_mut().map(f)
+}
+
+// makes the code running right now the first thread of this CPU and creates its idle thread
+// needs heap and VMM, has to run once on every CPU that should run threads
+pub fn init() {
+    let boot = Box::new(Thread { id: ThreadId::new(), state: State::Running, rsp: 0, stack: None });
+    let idle = Thread::spawn(Box::new(idle));
+    let scheduler = Scheduler {
+        current: boot.id,
+        idle: idle.id,
+        threads: BTreeMap::from([(boot.id, boot), (idle.id, idle)]),
+        ready: VecDeque::new(),
+        sleeping: Vec::new(),
+        dead: Vec::new(),
+        slice_start: Instant::now(),
+    };
+
+    interrupts::without_interrupts(|| {
+        let mut slot = SCHEDULERS[smp::cpu_id()].lock();
+        assert!(slot.is_none(), "threads already initialized on this CPU");
+        *slot = Some(scheduler);
+    });
+    time::set_tick_hook(preempt); // PIT ticks, application processors get preempted by their local APIC timer
+}
+
+// called by timer interrupts after EOI, switches threads when the current one used up its time slice
+// the interrupted thread continues from here (and returns from its interrupt) once it's scheduled again
+pub(crate) fn preempt() {
+    let now = Instant::now();
+    let switch = with_scheduler(|scheduler| {
+        scheduler.wake_sleepers(now);
+        scheduler.should_preempt(now).then(|| scheduler.switch_out(now)).flatten()
+    });
+    if let Some(Some((old_rsp, new_rsp))) = switch {
+        unsafe { switch_context(old_rsp, new_rsp) };
+    }
+}
+
+// gives up the CPU to whatever 'switch_out' picks, returns when this thread runs again
+fn schedule() {
+    interrupts::without_interrupts(|| {
+        // scheduler lock is released before switching, the next thread might need it right away
+        if let Some(Some((old_rsp, new_rsp))) = with_scheduler(|scheduler| scheduler.switch_out(Instant::now())) {
+            unsafe { switch_context(old_rsp, new_rsp) };
+        }
+    });
+}
+
+// runs a new thread on this CPU, it's picked up after the threads already waiting
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    reap();
+    let packet = Arc::new(Packet { state: Mutex::new(PacketState { result: None, joiner: None }) });
+    let result = packet.clone();
+    let thread = Thread::spawn(Box::new(move || result.finish(f())));
+    let id = thread.id;
+
+    interrupts::without_interrupts(|| {
+        with_scheduler(|scheduler| {
+            scheduler.threads.insert(id, thread);
+            scheduler.ready.push_back(id);
+        })
+    })
+    .expect("thread::init wasn't called on this CPU");
+    JoinHandle { id, packet }
+}
+
+// lets other ready threads run first
+pub fn yield_now() {
+    schedule();
+}
+
+// blocks this thread for at least 'duration', busy waits on a CPU without threads
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let slept = interrupts::without_interrupts(|| {
+        let blocked = with_scheduler(|scheduler| {
+            let current = scheduler.current;
+            scheduler.threads.get_mut(&current).expect("current thread is known").state = State::Sleeping(deadline);
+            scheduler.sleeping.push((deadline, current));
+        });
+        if blocked.is_some() {
+            schedule(); // woken by the first switch or timer interrupt after the deadline
+        }
+        blocked.is_some()
+    });
+    if !slept {
+        while Instant::now() < deadline {
+            instructions::hlt();
+        }
+    }
+}
+
+// None before 'init' ran on this CPU
+pub fn current_id() -> Option<ThreadId> {
+    interrupts::without_interrupts(|| with_scheduler(|scheduler| scheduler.current))
+}
+
+// ends the calling thread, its stack is freed later by another thread of this CPU
+pub fn exit() -> ! {
+    interrupts::disable(); // stays disabled until another thread takes over
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        scheduler.threads.get_mut(&current).expect("current thread is known").state = State::Finished;
+    })
+    .expect("thread::init wasn't called on this CPU");
+    schedule();
+    unreachable!("finished thread was scheduled again");
+}
+
+// marks the current thread blocked, it doesn't run again after the next 'schedule' until someone calls 'wake'
+fn block_current() -> (usize, ThreadId) {
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        scheduler.threads.get_mut(&current).expect("current thread is known").state = State::Blocked;
+        (smp::cpu_id(), current)
+    })
+    .expect("thread::init wasn't called on this CPU")
+}
+
+// makes a blocked thread of any CPU ready again
+fn wake(cpu: usize, id: ThreadId) {
+    if let Some(scheduler) = SCHEDULERS[cpu].lock().as_mut() {
+        scheduler.wake(id);
+    }
+}
+
+// frees stacks of finished threads on this CPU
+fn reap() {
+    let dead = interrupts::without_interrupts(|| with_scheduler(|scheduler| mem::take(&mut scheduler.dead)));
+    for thread in dead.into_iter().flatten() {
+        if let Some(stack) = thread.stack {
+            // thread switched away for the last time before it got here
+            unsafe { stack.free() }.expect("failed to free thread stack");
+        }
+    }
+}
+
+fn idle() {
+    loop {
+        reap();
+        interrupts::enable_and_hlt(); // next timer interrupt switches to a thread that became ready
+    }
+}
+
+// called by 'thread_start' on the new thread's stack
+extern "sysv64" fn thread_main(main: *mut Box<dyn FnOnce() + Send>) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    interrupts::enable(); // switch happened with interrupts disabled
+    main();
+    exit()
+}
+
+// result of a thread and who is waiting for it, lock is taken before the scheduler lock
+struct Packet<T> {
+    state: Mutex<PacketState<T>>,
+}
+
+struct PacketState<T> {
+    result: Option<T>,
+    joiner: Option<(usize, ThreadId)>, // CPU and thread blocked in 'join'
+}
+
+impl<T> Packet<T> {
+    fn finish(&self, value: T) {
+        interrupts::without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.result = Some(value);
+            if let Some((cpu, id)) = state.joiner.take() {
+                wake(cpu, id);
+            }
+        });
+    }
+}
+
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        interrupts::without_interrupts(|| self.packet.state.lock().result.is_some())
+    }
+
+    // blocks until the thread returned, needs 'init' on the calling CPU
+    pub fn join(self) -> T {
+        loop {
+            let result = interrupts::without_interrupts(|| {
+                let mut state = self.packet.state.lock();
+                if let Some(value) = state.result.take() {
+                    return Some(value);
+                }
+                // blocked before the packet lock is released, so 'finish' can't wake us too early
+                state.joiner = Some(block_current());
+                drop(state);
+                schedule();
+                None
+            });
+            if let Some(value) = result {
+                return value;
+            }
+        }
+    }
+}
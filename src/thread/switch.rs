@@ -0,0 +1,48 @@
+use core::arch::naked_asm;
+
+// callee-saved registers as 'switch_context' pushes them, lowest address first
+// a new thread's stack starts with this frame, so the first switch to it "returns" into 'thread_start'
+#[repr(C)]
+pub(super) struct InitialFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64, // entry argument, handed to 'thread_main' by 'thread_start'
+    pub rbx: u64,
+    pub rbp: u64, // 0 ends stack walks
+    pub ret: u64,
+}
+
+// saves callee-saved registers on the current stack, stores its pointer to 'old_rsp', then continues on 'new_rsp'
+// everything else is either caller-saved (compiler spills it around the call) or saved by the interrupt entry
+// has to be called with interrupts disabled, the thread switched to restores its own flags when it continues
+#[unsafe(naked)]
+pub(super) unsafe extern "sysv64" fn switch_context(old_rsp: *mut u64, new_rsp: u64) {
+    naked_asm!(r#"
+    push %rbp
+    push %rbx
+    push %r12
+    push %r13
+    push %r14
+    push %r15
+    mov %rsp, (%rdi)
+    mov %rsi, %rsp
+    pop %r15
+    pop %r14
+    pop %r13
+    pop %r12
+    pop %rbx
+    pop %rbp
+    ret
+    "#, options(att_syntax));
+}
+
+// first instructions of every spawned thread, stack is 16 byte aligned here, so the call leaves it as the ABI expects
+#[unsafe(naked)]
+pub(super) unsafe extern "sysv64" fn thread_start() -> ! {
+    naked_asm!(r#"
+    mov %r12, %rdi
+    call {main}
+    ud2
+    "#, main = sym super::thread_main, options(att_syntax));
+}
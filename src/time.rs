@@ -0,0 +1,144 @@
+use core::arch::x86_64::_rdtsc;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::interrupts::{self, pit};
+
+pub const TICK_FREQUENCY: u32 = 1000; // PIT interrupts per second
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const CALIBRATION_MS: u32 = 20;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static PIT_DIVISOR: AtomicU64 = AtomicU64::new(0);
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0); // Hz, 0 until calibrated
+static TSC_AT_BOOT: AtomicU64 = AtomicU64::new(0);
+// called at the end of every tick, 'thread::init' makes it preempt the running thread
+static TICK_HOOK: spin::Once<fn()> = spin::Once::new();
+
+// point in time since boot with nanosecond resolution, only goes forward
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant { nanos: now_nanos() }
+    }
+
+    pub const fn from_nanos(nanos: u64) -> Instant {
+        Instant { nanos }
+    }
+
+    // time since boot
+    pub const fn as_nanos(self) -> u64 {
+        self.nanos
+    }
+
+    // saturates to zero when 'earlier' is actually later
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, duration: Duration) -> Option<Instant> {
+        let nanos = u64::try_from(duration.as_nanos()).ok()?;
+        Some(Instant { nanos: self.nanos.checked_add(nanos)? })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+// programs PIT to TICK_FREQUENCY, starts counting ticks and calibrates TSC against it
+// needs IDT and PICs initialized
+pub fn init() {
+    let divisor = pit::set_frequency(TICK_FREQUENCY);
+    PIT_DIVISOR.store(divisor as u64, Ordering::Relaxed);
+    interrupts::register_irq(0, timer_interrupt).expect("timer IRQ already taken");
+    calibrate_tsc();
+}
+
+// 'hook' runs in interrupt context on every tick, after the interrupt is acknowledged (it may switch threads)
+// only the first hook set is kept
+pub fn set_tick_hook(hook: fn()) {
+    TICK_HOOK.call_once(|| hook);
+}
+
+fn timer_interrupt() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    if let Some(hook) = TICK_HOOK.r#try() {
+        hook(); // the next thread may run for a while before this returns
+    }
+}
+
+// timer interrupts since 'init'
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_nanos(now_nanos())
+}
+
+// None when calibration didn't happen (yet)
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// converts a TSC cycle count (e.g. difference of two 'rdtsc' calls), zero when TSC isn't calibrated
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    match tsc_frequency() {
+        Some(hz) => Duration::from_nanos((cycles as u128 * NANOS_PER_SEC as u128 / hz as u128) as u64),
+        None => Duration::ZERO,
+    }
+}
+
+// counts TSC cycles during a PIT measured busy wait
+fn calibrate_tsc() {
+    let start = rdtsc();
+    pit::sleep_ms(CALIBRATION_MS);
+    let cycles = rdtsc() - start;
+
+    TSC_AT_BOOT.store(start, Ordering::Relaxed);
+    TSC_FREQUENCY.store(cycles * 1000 / CALIBRATION_MS as u64, Ordering::Relaxed);
+}
+
+// TSC gives nanosecond resolution, ticks are the fallback (millisecond resolution)
+fn now_nanos() -> u64 {
+    match tsc_frequency() {
+        Some(hz) => {
+            let cycles = rdtsc().saturating_sub(TSC_AT_BOOT.load(Ordering::Relaxed));
+            (cycles as u128 * NANOS_PER_SEC as u128 / hz as u128) as u64
+        }
+        None => {
+            let divisor = PIT_DIVISOR.load(Ordering::Relaxed);
+            (ticks() as u128 * divisor as u128 * NANOS_PER_SEC as u128 / pit::PIT_FREQUENCY as u128) as u64
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use volatile::Volatile;
 use core::fmt;
-use lazy_static::lazy_static;
 use spin::Mutex;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::instructions::interrupts;
 
+use crate::output::{self, Target};
+use crate::smp;
+
+mod cursor;
+
 const VGA_BUFFER_ADDR: u64 = 0xb8000;
 
 #[macro_export] // makes macro available everywhere in crate
@@ -22,83 +27,244 @@ macro_rules! println {
 // needs to be public bcs macros need to be able to call it from outside the module
 #[doc(hidden)] // but its considered private, so this attribute hides it from the generated docs
 pub fn _print(args: fmt::Arguments) {
+    output::write(Target::Vga, args); // buffered, see 'output'
+}
+
+// writes right away, bypassing the buffer of 'print!'
+// false when this CPU is already in the middle of printing (e.g. an exception hit while it held the writer),
+// output is dropped then instead of spinning on a lock that can never be released
+pub fn try_print(args: fmt::Arguments) -> bool {
+    with_writer(|writer| {
+        let _ = writer.write_fmt(args); // a failing Display impl only cuts its own output short
+    })
+}
+
+// bytes that aren't printable ASCII show up as ■, like in 'try_print'
+pub(crate) fn write_bytes(bytes: &[u8]) -> bool {
+    with_writer(|writer| writer.write_bytes(bytes))
+}
+
+fn with_writer(f: impl FnOnce(&mut Writer)) -> bool {
     interrupts::without_interrupts(|| { // disabling interrupts so we omit deadlocks in case an interrupt would try to write something
-        WRITER
-        .lock()
-        .write_fmt(args)
-        .unwrap();
-    });
+        let cpu = smp::cpu_id();
+        if WRITER_OWNER.load(Ordering::Acquire) == cpu {
+            return false;
+        }
+        let mut writer = WRITER.lock();
+        WRITER_OWNER.store(cpu, Ordering::Release);
+        f(&mut writer);
+        WRITER_OWNER.store(NO_OWNER, Ordering::Release);
+        true
+    })
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+// panic path, takes the writer away from this CPU if the panic interrupted it while printing,
+// or from another CPU that doesn't release it within 'patience' spins
+pub(crate) fn force_unlock(patience: usize) {
+    let held_here = WRITER_OWNER.load(Ordering::Acquire) == smp::cpu_id();
+    if held_here || !(0..patience).any(|_| WRITER.try_lock().is_some()) {
+        unsafe { WRITER.force_unlock() };
+        WRITER_OWNER.store(NO_OWNER, Ordering::Release);
+    }
+    with_writer(|writer| writer.set_view(0)); // the panic message shouldn't end up below a scrolled back view
+}
 
-lazy_static! { // lazy static needed here, as Rust’s const evaluator is not able to convert raw pointers to references at compile time
-    static ref WRITER: Mutex<Writer> = Mutex::new(Writer { // Mutex needed so it can be mutable
-        column_position: 0,
-        color_code: ColorCode::new(Color::Magenta, Color::Black),
-        buffer: unsafe { &mut *(VGA_BUFFER_ADDR as *mut Buffer)}, // VGA buffers memmory address is 0xb8000
-    });
+const NO_OWNER: usize = usize::MAX;
+
+// CPU holding WRITER
+static WRITER_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
+
+const SCROLLBACK_LINES: usize = 200; // lines kept after they scroll off the top
+const TAB_WIDTH: usize = 8;
+const BACKSPACE: u8 = 0x08;
+const DEFAULT_COLOR: ColorCode = ColorCode::new(Color::Magenta, Color::Black);
+
+static WRITER: Mutex<Writer> = Mutex::new(Writer::new()); // Mutex needed so it can be mutable
+
+// clears the screen, the cursor goes to the top left corner, cleared lines can still be scrolled back to
+pub fn clear() {
+    output::flush_blocking(); // what was printed before should be cleared too, even while the flusher is busy
+    with_writer(|writer| writer.clear());
+}
+
+// writes 'text' starting at the given position, without moving the cursor
+// doesn't wrap, whatever doesn't fit the screen is cut off, control characters show up as ■
+pub fn write_at(row: usize, column: usize, text: &str) {
+    output::flush_blocking(); // so earlier prints can't scroll over it later
+    with_writer(|writer| writer.write_at(row, column, text));
+}
+
+// shows 'lines' lines further back in the scrollback, as far as it goes
+pub fn scroll_up(lines: usize) {
+    with_writer(|writer| writer.set_view(writer.view_offset.saturating_add(lines)));
+}
+
+pub fn scroll_down(lines: usize) {
+    with_writer(|writer| writer.set_view(writer.view_offset.saturating_sub(lines)));
 }
 
+pub fn scroll_to_bottom() {
+    with_writer(|writer| writer.set_view(0));
+}
+
+// lines the view is scrolled back, 0 when it shows the current screen
+pub fn scroll_offset() -> usize {
+    interrupts::without_interrupts(|| WRITER.lock().view_offset)
+}
+
+// row and column the next character goes to
+pub fn cursor() -> (usize, usize) {
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        (writer.row, writer.column_position)
+    })
+}
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
 struct Writer {
+    row: usize,
     column_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    screen: [Line; BUFFER_HEIGHT], // what's on screen when not scrolled back, the VGA buffer is only written to
+    scrollback: Scrollback,
+    view_offset: usize, // lines scrolled back
+    cursor_shown: bool,
 }
 
 impl Writer {
+    const fn new() -> Writer {
+        Writer {
+            row: BUFFER_HEIGHT - 1, // starts at the bottom, below whatever the bootloader left on screen
+            column_position: 0,
+            color_code: DEFAULT_COLOR,
+            screen: [[ScreenChar::blank(DEFAULT_COLOR); BUFFER_WIDTH]; BUFFER_HEIGHT],
+            scrollback: Scrollback::new(),
+            view_offset: 0,
+            cursor_shown: false,
+        }
+    }
+
+    // a function bcs Rust’s const evaluator is not able to convert raw pointers to references, so WRITER can't hold it
+    // only used while holding WRITER
+    fn buffer() -> &'static mut Buffer {
+        unsafe { &mut *(VGA_BUFFER_ADDR as *mut Buffer) } // VGA buffers memmory address is 0xb8000
+    }
+
     fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
+        self.write_bytes(s.as_bytes());
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
             match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte), // int UTF-8 the individual bytes of multi-byte values are never valid ASCII
+                // printable ASCII byte or one of the control characters we handle
+                0x20..=0x7e | b'\n' | b'\r' | b'\t' | BACKSPACE => self.write_byte(byte), // int UTF-8 the individual bytes of multi-byte values are never valid ASCII
                 // not part of printable ASCII range
                 _ => self.write_byte(0xfe), // 0xfe is ■
             }
         }
+        self.update_cursor(); // once per write, port I/O is slow
     }
+
     fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            b'\t' => {
+                let spaces = TAB_WIDTH - self.column_position % TAB_WIDTH;
+                for _ in 0..spaces {
+                    self.write_byte(b' ');
+                }
+            }
+            BACKSPACE => { // erases the character before the cursor, stops at the start of the row
+                if self.column_position > 0 {
+                    self.column_position -= 1;
+                    self.put(self.row, self.column_position, ScreenChar::blank(self.color_code));
+                }
+            }
             byte => {
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
                 }
-
-                let row = BUFFER_HEIGHT - 1; // always write to bottom row
-                let col = self.column_position;
-                let color_code = self.color_code;
-                
-                // using Volatile write to directly write data to VGA buffer
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code
-                });
+                let character = ScreenChar { ascii_character: byte, color_code: self.color_code };
+                self.put(self.row, self.column_position, character);
                 self.column_position += 1;
             }
         }
     }
 
+    fn write_at(&mut self, row: usize, column: usize, text: &str) {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        for (column, byte) in (column..BUFFER_WIDTH).zip(text.bytes()) {
+            let ascii_character = if (0x20..=0x7e).contains(&byte) { byte } else { 0xfe };
+            self.put(row, column, ScreenChar { ascii_character, color_code: self.color_code });
+        }
+    }
+
+    fn put(&mut self, row: usize, column: usize, character: ScreenChar) {
+        self.screen[row][column] = character;
+        if self.view_offset == 0 { // while scrolled back the screen is updated once the view comes back
+            Writer::buffer().chars[row][column].write(character); // using Volatile write to directly write data to VGA buffer
+        }
+    }
+
     fn new_line(&mut self) {
-        // move each character one row up
-        for row in 1..BUFFER_HEIGHT { // omiting 0th row, it'll be shifted off screen (overwritten in practice)
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        self.column_position = 0;
+        if self.row < BUFFER_HEIGHT - 1 {
+            self.row += 1;
+            return;
+        }
+        // move each row one up, the top one goes to the scrollback
+        self.scrollback.push(self.screen[0]);
+        self.screen.copy_within(1.., 0);
+        self.screen[BUFFER_HEIGHT - 1] = [ScreenChar::blank(self.color_code); BUFFER_WIDTH]; // clear row at the bottom
+        if self.view_offset > 0 {
+            self.view_offset += 1; // keeps showing the same lines while scrolled back
         }
-        self.clear_row(BUFFER_HEIGHT - 1); // clear row at the bottom
+        self.set_view(self.view_offset);
+    }
+
+    fn clear(&mut self) {
+        for row in 0..=self.row {
+            self.scrollback.push(self.screen[row]);
+        }
+        self.screen = [[ScreenChar::blank(self.color_code); BUFFER_WIDTH]; BUFFER_HEIGHT];
+        self.row = 0;
         self.column_position = 0;
+        self.set_view(0);
     }
 
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+    // redraws the screen 'offset' lines back in the scrollback
+    fn set_view(&mut self, offset: usize) {
+        self.view_offset = offset.min(self.scrollback.len());
+        let buffer = Writer::buffer();
+        for row in 0..BUFFER_HEIGHT {
+            let line = match row.checked_sub(self.view_offset) {
+                Some(screen_row) => &self.screen[screen_row],
+                None => self.scrollback.line(self.scrollback.len() - self.view_offset + row),
+            };
+            for (column, character) in line.iter().enumerate() {
+                buffer.chars[row][column].write(*character);
+            }
+        }
+        self.update_cursor();
+    }
+
+    // hidden while scrolled back, the cursor would point at old output otherwise
+    fn update_cursor(&mut self) {
+        let shown = self.view_offset == 0;
+        if shown {
+            cursor::move_to((self.row * BUFFER_WIDTH + self.column_position.min(BUFFER_WIDTH - 1)) as u16);
+        }
+        if shown != self.cursor_shown {
+            if shown { cursor::show() } else { cursor::hide() }
+            self.cursor_shown = shown;
         }
     }
 }
@@ -111,6 +277,38 @@ impl fmt::Write for Writer {
     }
 }
 
+// lines that scrolled off the screen, the oldest ones are overwritten once it's full
+struct Scrollback {
+    lines: [Line; SCROLLBACK_LINES],
+    start: usize, // oldest line
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback { lines: [[ScreenChar::blank(DEFAULT_COLOR); BUFFER_WIDTH]; SCROLLBACK_LINES], start: 0, len: 0 }
+    }
+
+    fn push(&mut self, line: Line) {
+        if self.len == SCROLLBACK_LINES {
+            self.lines[self.start] = line;
+            self.start = (self.start + 1) % SCROLLBACK_LINES;
+        } else {
+            self.lines[(self.start + self.len) % SCROLLBACK_LINES] = line;
+            self.len += 1;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // 0 is the oldest line
+    fn line(&self, index: usize) -> &Line {
+        &self.lines[(self.start + index) % SCROLLBACK_LINES]
+    }
+}
+
  // can only be used on a struct that has a single non-zero-sized field
 #[repr(transparent)] // guarantees the layout to be the same as that one field
 struct Buffer {
@@ -124,6 +322,12 @@ struct ScreenChar { // first byte represents the character, second byte defines
     color_code: ColorCode,
 }
 
+impl ScreenChar {
+    const fn blank(color_code: ColorCode) -> ScreenChar {
+        ScreenChar { ascii_character: b' ', color_code }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -177,8 +381,61 @@ fn test_println_output() {
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed"); // use writeln bcs it allows writing on already locked writer
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = Writer::buffer().chars[BUFFER_HEIGHT - 2][i].read();
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
-}
\ No newline at end of file
+}
+
+// text of a row as it's on screen
+#[cfg(test)]
+fn screen_row(row: usize) -> [u8; BUFFER_WIDTH] {
+    core::array::from_fn(|column| Writer::buffer().chars[row][column].read().ascii_character)
+}
+
+#[test_case]
+fn test_control_characters() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nab\tc\rx\x08y\x08\x08").expect("write failed");
+        assert_eq!(&screen_row(writer.row)[..10], b" b      c "); // 'x' replaced 'a', then it and 'y' were erased
+        assert_eq!((writer.row, writer.column_position), (BUFFER_HEIGHT - 1, 0));
+    });
+}
+
+#[test_case]
+fn test_write_at_keeps_cursor() {
+    let before = cursor();
+    write_at(3, BUFFER_WIDTH - 4, "cut off\n");
+    assert_eq!(&screen_row(3)[BUFFER_WIDTH - 4..], b"cut ");
+    assert_eq!(cursor(), before);
+}
+
+#[test_case]
+fn test_clear() {
+    println!("before clear");
+    clear();
+    println!("after clear");
+    assert_eq!(&screen_row(0)[..11], b"after clear");
+    assert_eq!(cursor(), (1, 0));
+}
+
+#[test_case]
+fn test_scrollback() {
+    clear();
+    for i in 0..BUFFER_HEIGHT + 5 {
+        println!("line {:02}", i);
+    }
+    scroll_up(3);
+    assert_eq!(scroll_offset(), 3);
+    assert_eq!(&screen_row(0)[..7], b"line 03");
+    println!("new line");
+    assert_eq!(&screen_row(0)[..7], b"line 03"); // output while scrolled back doesn't move the view
+    scroll_down(1);
+    assert_eq!(&screen_row(0)[..7], b"line 04");
+    scroll_up(usize::MAX);
+    assert!(scroll_offset() <= SCROLLBACK_LINES);
+    scroll_to_bottom();
+    assert_eq!(scroll_offset(), 0);
+    assert_eq!(&screen_row(BUFFER_HEIGHT - 2)[..8], b"new line");
+}
@@ -0,0 +1,53 @@
+use x86_64::instructions::port::Port;
+
+// CRT controller, registers are selected through the address port and accessed through the data port
+const CRTC_ADDRESS: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+
+const CURSOR_START: u8 = 0x0a; // first scanline of the cursor, bit 5 hides it
+const CURSOR_END: u8 = 0x0b; // last scanline
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+
+const CURSOR_DISABLE: u8 = 1 << 5;
+// underline, bottom two of the 16 scanlines of a character, the hardware makes it blink
+const FIRST_SCANLINE: u8 = 14;
+const LAST_SCANLINE: u8 = 15;
+
+pub(super) fn show() {
+    unsafe {
+        // upper bits of both registers aren't cursor related
+        write(CURSOR_START, read(CURSOR_START) & 0xc0 | FIRST_SCANLINE);
+        write(CURSOR_END, read(CURSOR_END) & 0xe0 | LAST_SCANLINE);
+    }
+}
+
+pub(super) fn hide() {
+    unsafe { write(CURSOR_START, CURSOR_DISABLE) };
+}
+
+// 'position' counts characters from the top left corner, row by row
+pub(super) fn move_to(position: u16) {
+    unsafe {
+        write(CURSOR_LOCATION_LOW, position as u8);
+        write(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+}
+
+unsafe fn write(register: u8, value: u8) {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS);
+    let mut data: Port<u8> = Port::new(CRTC_DATA);
+    unsafe {
+        address.write(register);
+        data.write(value);
+    }
+}
+
+unsafe fn read(register: u8) -> u8 {
+    let mut address: Port<u8> = Port::new(CRTC_ADDRESS);
+    let mut data: Port<u8> = Port::new(CRTC_DATA);
+    unsafe {
+        address.write(register);
+        data.read()
+    }
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ruost::interrupts::{self, apic, pit, InterruptController, InterruptIndex};
+use ruost::{acpi, allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    acpi::init();
+    interrupts::init_apic();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_is_parsed() {
+    let info = acpi::info().expect("QEMU provides ACPI tables");
+    assert!(info.processors.iter().any(|p| p.enabled));
+    assert!(!info.io_apics.is_empty());
+}
+
+#[test_case]
+fn apic_replaces_pics() {
+    assert_eq!(interrupts::controller(), InterruptController::Apic);
+    let local_apic = apic::local_apic().unwrap();
+    let bsp = acpi::info().unwrap().processors[0].apic_id;
+    assert_eq!(local_apic.id(), bsp);
+}
+
+#[test_case]
+fn periodic_timer_ticks() {
+    let before = apic::timer_ticks();
+    pit::sleep_ms(100); // 10 ticks at 100 Hz
+    let ticks = apic::timer_ticks() - before;
+    assert!((5..=20).contains(&ticks), "unexpected tick count {}", ticks);
+}
+
+#[test_case]
+fn one_shot_timer_fires_once() {
+    apic::stop_timer();
+    let before = apic::timer_ticks();
+    apic::start_one_shot_timer(InterruptIndex::ApicTimer.as_u8(), 1000);
+    pit::sleep_ms(20);
+    assert_eq!(apic::timer_ticks(), before + 1);
+    assert_eq!(apic::local_apic().unwrap().timer_remaining(), 0);
+    apic::start_periodic_timer(InterruptIndex::ApicTimer.as_u8(), apic::TIMER_FREQUENCY);
+}
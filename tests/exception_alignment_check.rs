@@ -0,0 +1,12 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// #AC is only checked at CPL 3, the kernel has no ring 3 code to raise it from
+// so it's raised in software, 'int' doesn't push an error code and the handler takes the return address for one and reads the frame shifted
+// this only checks the handler is reached, not the reported error code or frame
+ruost::fatal_exception_test!(Exception::AlignmentCheck, {
+    unsafe { asm!("int 17") }
+});
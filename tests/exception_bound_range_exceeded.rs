@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// BOUND instruction doesn't exist in long mode, so the exception is raised in software
+ruost::fatal_exception_test!(Exception::BoundRangeExceeded, {
+    unsafe { asm!("int 5") }
+});
@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::DeviceNotAvailable, {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    // with task switched flag set, the next x87 instruction faults (used for lazy FPU state switching)
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::TASK_SWITCHED);
+        });
+        asm!("fninit");
+    }
+});
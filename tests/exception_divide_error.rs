@@ -0,0 +1,11 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::DivideError, {
+    unsafe {
+        asm!("div ecx", in("ecx") 0, inout("eax") 1 => _, inout("edx") 0 => _);
+    }
+});
@@ -0,0 +1,15 @@
+#![no_std]
+#![no_main]
+
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::DoubleFault, {
+    #[allow(unconditional_recursion)]
+    fn stack_overflow() {
+        stack_overflow();
+        volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+    }
+
+    // page fault can't push its frame on the overflowed stack, CPU escalates to double fault running on IST stack
+    stack_overflow();
+});
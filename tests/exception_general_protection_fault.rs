@@ -0,0 +1,12 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::GeneralProtectionFault, {
+    // non-canonical address
+    unsafe {
+        asm!("mov {tmp}, [{addr}]", addr = in(reg) 0x_8000_0000_0000_u64, tmp = out(reg) _);
+    }
+});
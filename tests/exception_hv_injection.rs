@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// only raised by hypervisors, so the exception is raised in software
+ruost::fatal_exception_test!(Exception::HvInjection, {
+    unsafe { asm!("int 28") }
+});
@@ -0,0 +1,9 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::InvalidOpcode, {
+    unsafe { asm!("ud2") }
+});
@@ -0,0 +1,12 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// in long mode #TS only comes from a stack switch to a more privileged ring, the kernel has no ring 3 code to start from
+// so it's raised in software, 'int' doesn't push an error code and the handler takes the return address for one and reads the frame shifted
+// this only checks the handler is reached, not the reported error code or frame
+ruost::fatal_exception_test!(Exception::InvalidTss, {
+    unsafe { asm!("int 10") }
+});
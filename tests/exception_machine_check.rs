@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// hardware errors can't be provoked in QEMU, so the exception is raised in software
+ruost::fatal_exception_test!(Exception::MachineCheck, {
+    unsafe { asm!("int 18") }
+});
@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::PageFault, {
+    unsafe {
+        *(0x_dead_beaf_0000 as *mut u64) = 42;
+    }
+});
@@ -0,0 +1,12 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// only raised by SVM on a redirected INIT, which QEMU doesn't do to its guest
+// so it's raised in software, 'int' doesn't push an error code and the handler takes the return address for one and reads the frame shifted
+// this only checks the handler is reached, not the reported error code or frame
+ruost::fatal_exception_test!(Exception::Security, {
+    unsafe { asm!("int 30") }
+});
@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+use x86_64::instructions::tables::lgdt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::structures::gdt::DescriptorFlags;
+use x86_64::VirtAddr;
+
+const NOT_PRESENT_SELECTOR: u64 = 2 << 3; // index 2, GDT, ring 0
+
+// same code segment as the kernel GDT (so 'cs' stays valid), plus a data segment with the present bit cleared
+// both have the accessed bit set, so the CPU never writes to this (read-only) table
+static GDT: [u64; 3] = [
+    0,
+    DescriptorFlags::KERNEL_CODE64.bits(),
+    DescriptorFlags::KERNEL_DATA.bits() & !DescriptorFlags::PRESENT.bits(),
+];
+
+// loading a data segment register with a not present descriptor raises #NP with its selector as error code
+ruost::fatal_exception_test!(Exception::SegmentNotPresent, error_code = NOT_PRESENT_SELECTOR, {
+    unsafe {
+        lgdt(&DescriptorTablePointer {
+            limit: (core::mem::size_of_val(&GDT) - 1) as u16,
+            base: VirtAddr::from_ptr(GDT.as_ptr()),
+        });
+        asm!("mov ds, {0:x}", in(reg) NOT_PRESENT_SELECTOR as u16);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// SSE is disabled for the kernel (soft-float target), so the exception is raised in software
+ruost::fatal_exception_test!(Exception::SimdFloatingPoint, {
+    unsafe { asm!("int 19") }
+});
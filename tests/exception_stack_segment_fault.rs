@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+ruost::fatal_exception_test!(Exception::StackSegmentFault, {
+    // memory accesses based on rbp use the stack segment, non-canonical address there raises #SS instead of #GP
+    unsafe {
+        asm!(
+            "push rbp",
+            "mov rbp, {addr}",
+            "mov {tmp}, [rbp]",
+            "pop rbp",
+            addr = in(reg) 0x_8000_0000_0000_u64,
+            tmp = out(reg) _,
+        );
+    }
+});
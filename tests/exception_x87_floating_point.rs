@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use ruost::interrupts::exceptions::Exception;
+
+// FPU is not used by the kernel (soft-float target), so the exception is raised in software
+ruost::fatal_exception_test!(Exception::X87FloatingPoint, {
+    unsafe { asm!("int 16") }
+});
@@ -0,0 +1,53 @@
+// exceptions which are only reported, execution continues after them
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use ruost::interrupts::exceptions::{exception_count, Exception};
+use ruost::{halt, init};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    init();
+    test_main();
+    halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+#[test_case]
+fn debug_exception() {
+    let before = exception_count(Exception::Debug);
+    unsafe { asm!(".byte 0xf1") }; // INT1 (ICEBP), raises #DB like a hardware breakpoint would
+    assert_eq!(exception_count(Exception::Debug), before + 1);
+}
+
+#[test_case]
+fn non_maskable_interrupt() {
+    let before = exception_count(Exception::NonMaskableInterrupt);
+    unsafe { asm!("int 2") };
+    assert_eq!(exception_count(Exception::NonMaskableInterrupt), before + 1);
+}
+
+#[test_case]
+fn breakpoint_exception() {
+    let before = exception_count(Exception::Breakpoint);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(exception_count(Exception::Breakpoint), before + 1);
+}
+
+#[test_case]
+fn overflow_exception() {
+    // INTO doesn't exist in long mode, so the exception is raised in software
+    let before = exception_count(Exception::Overflow);
+    unsafe { asm!("int 4") };
+    assert_eq!(exception_count(Exception::Overflow), before + 1);
+}
@@ -8,7 +8,8 @@
 extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
-use ruost::allocator::HEAP_SIZE;
+use ruost::allocator::{self, HEAP_MAX_SIZE, HEAP_SIZE};
+use ruost::memory;
 use core::panic::PanicInfo;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
@@ -16,10 +17,9 @@ use alloc::vec::Vec;
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use ruost::allocator;
-
     ruost::init();
-    allocator::init(boot_info);
+    memory::init(boot_info);
+    allocator::init();
     test_main();
     ruost::halt()
 }
@@ -63,4 +63,33 @@ fn many_boxes_long_lived() {
         assert_eq!(*x, i);
     }
     assert_eq!(*long_lived, 1);
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn heap_grows_past_initial_size() {
+    let size = 4 * 1024 * 1024; // 4 MiB, way more than initial heap
+    let mut vec = Vec::with_capacity(size);
+    vec.resize(size, 0xabu8);
+    assert!(allocator::heap_size() > HEAP_SIZE);
+    assert!(allocator::heap_size() <= HEAP_MAX_SIZE);
+    assert!(vec.iter().all(|&b| b == 0xab));
+}
+
+#[test_case]
+fn many_long_lived_boxes_past_initial_size() {
+    let mut boxes = Vec::new();
+    for i in 0..(2 * HEAP_SIZE / 1024) {
+        boxes.push(Box::new([i as u8; 1024]));
+    }
+    for (i, b) in boxes.iter().enumerate() {
+        assert!(b.iter().all(|&x| x == i as u8));
+    }
+}
+
+#[test_case]
+fn allocation_past_ceiling_fails() {
+    use alloc::alloc::{alloc, Layout};
+
+    let layout = Layout::from_size_align(HEAP_MAX_SIZE + 1, 8).unwrap();
+    assert!(unsafe { alloc(layout) }.is_null());
+}
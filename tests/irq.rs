@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ruost::interrupts::{self, irq_count, register_irq, unregister_irq, IrqError};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    ruost::init();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn count_call() {
+    CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn other_handler() {}
+
+// raises IRQ5 vector in software, goes through the same stub as a real interrupt
+fn raise_irq5() {
+    unsafe { asm!("int {}", const interrupts::irq::vector(5)) };
+}
+
+#[test_case]
+fn registered_handler_is_called() {
+    let before = irq_count(5);
+    register_irq(5, count_call).unwrap();
+    raise_irq5();
+    raise_irq5();
+    assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+    assert_eq!(irq_count(5), before + 2);
+    assert!(unregister_irq(5).is_some());
+}
+
+#[test_case]
+fn line_can_only_have_one_handler() {
+    register_irq(5, count_call).unwrap();
+    assert_eq!(register_irq(5, other_handler), Err(IrqError::AlreadyRegistered));
+    assert!(unregister_irq(5).is_some());
+    assert!(unregister_irq(5).is_none());
+}
+
+#[test_case]
+fn unregistered_line_is_still_counted() {
+    CALLS.store(0, Ordering::Relaxed);
+    let before = irq_count(5);
+    raise_irq5();
+    assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+    assert_eq!(irq_count(5), before + 1);
+}
+
+#[test_case]
+fn invalid_line_is_rejected() {
+    assert_eq!(register_irq(16, count_call), Err(IrqError::InvalidIrq));
+    assert_eq!(irq_count(16), 0);
+}
+
+#[test_case]
+fn hardware_timer_is_counted() {
+    assert_eq!(register_irq(0, count_call), Err(IrqError::AlreadyRegistered)); // taken by time module
+    let before = irq_count(0);
+    x86_64::instructions::hlt();
+    x86_64::instructions::hlt();
+    assert!(irq_count(0) > before);
+}
@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ruost::task::executor::Executor;
+use ruost::task::keyboard::{self, DecodedKey, KeyCode, KeyDecoder, KeyEvent, KeyState, Layout};
+use ruost::task::Task;
+use ruost::{allocator, memory, ps2};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info); // LED commands wait on timers and wakers, they need the heap
+    allocator::init();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+// scancode set 1: release is the press code with bit 7 set
+const A: u8 = 0x1e;
+const Y: u8 = 0x15;
+const LSHIFT: u8 = 0x2a;
+const CAPS_LOCK: u8 = 0x3a;
+const NUM_LOCK: u8 = 0x45;
+const RELEASE: u8 = 0x80;
+const EXTENDED: u8 = 0xe0; // right alt is extended left alt
+const ALT: u8 = 0x38;
+
+fn press(decoder: &mut KeyDecoder, scancode: u8) -> KeyEvent {
+    decoder.add_byte(scancode).expect("single byte scancode")
+}
+
+fn type_key(decoder: &mut KeyDecoder, scancode: u8) -> Option<DecodedKey> {
+    let event = press(decoder, scancode);
+    press(decoder, scancode | RELEASE);
+    event.key
+}
+
+#[test_case]
+fn press_and_release() {
+    let mut decoder = KeyDecoder::new(Layout::Us);
+    let pressed = press(&mut decoder, A);
+    assert_eq!((pressed.code, pressed.state), (KeyCode::A, KeyState::Pressed));
+    assert_eq!(pressed.key, Some(DecodedKey::Unicode('a')));
+
+    let released = press(&mut decoder, A | RELEASE);
+    assert_eq!((released.code, released.state), (KeyCode::A, KeyState::Released));
+    assert_eq!(released.key, None);
+}
+
+#[test_case]
+fn shift_is_reported_and_applied() {
+    let mut decoder = KeyDecoder::new(Layout::Us);
+    assert!(press(&mut decoder, LSHIFT).modifiers.shift());
+    let event = press(&mut decoder, A);
+    assert!(event.modifiers.lshift);
+    assert_eq!(event.key, Some(DecodedKey::Unicode('A')));
+    assert!(!press(&mut decoder, LSHIFT | RELEASE).modifiers.shift());
+}
+
+#[test_case]
+fn lock_keys_toggle_on_press() {
+    let mut decoder = KeyDecoder::new(Layout::Us);
+    type_key(&mut decoder, CAPS_LOCK);
+    type_key(&mut decoder, NUM_LOCK);
+    assert!(decoder.locks().caps && decoder.locks().num && !decoder.locks().scroll);
+    assert_eq!(decoder.locks().leds(), 0b110);
+    assert_eq!(type_key(&mut decoder, A), Some(DecodedKey::Unicode('A')));
+
+    type_key(&mut decoder, CAPS_LOCK);
+    assert!(!decoder.locks().caps);
+}
+
+#[test_case]
+fn layout_can_be_switched() {
+    let mut decoder = KeyDecoder::new(Layout::Us);
+    assert_eq!(type_key(&mut decoder, Y), Some(DecodedKey::Unicode('y')));
+    decoder.set_layout(Layout::German);
+    assert_eq!(type_key(&mut decoder, Y), Some(DecodedKey::Unicode('z')));
+}
+
+#[test_case]
+fn polish_programmers_letters() {
+    let mut decoder = KeyDecoder::new(Layout::PolishProgrammers);
+    assert_eq!(type_key(&mut decoder, A), Some(DecodedKey::Unicode('a')));
+
+    assert_eq!(decoder.add_byte(EXTENDED), None);
+    assert!(press(&mut decoder, ALT).modifiers.alt_gr);
+    assert_eq!(type_key(&mut decoder, A), Some(DecodedKey::Unicode('ą')));
+    press(&mut decoder, LSHIFT);
+    assert_eq!(type_key(&mut decoder, A), Some(DecodedKey::Unicode('Ą')));
+}
+
+#[test_case]
+fn layout_names_round_trip() {
+    for layout in Layout::ALL {
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    keyboard::set_layout(Layout::Dvorak);
+    assert_eq!(keyboard::layout(), Layout::Dvorak);
+    keyboard::set_layout(Layout::Us);
+}
+
+#[test_case]
+fn keyboard_acknowledges_leds() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+        assert_eq!(ps2::set_keyboard_leds(0b111).await, Ok(()));
+        assert_eq!(ps2::set_keyboard_leds(0).await, Ok(()));
+        spawner.shutdown();
+    }));
+    executor.run();
+}
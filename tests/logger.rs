@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use log::LevelFilter;
+use ruost::logger::{self, Sink};
+use ruost::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    logger::init(LevelFilter::Info);
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+#[test_case]
+fn longest_module_filter_wins() {
+    logger::set_module_level("ruost::task", LevelFilter::Warn);
+    logger::set_module_level("ruost::task::keyboard", LevelFilter::Trace);
+    assert_eq!(logger::level_for("ruost::task::mouse"), LevelFilter::Warn);
+    assert_eq!(logger::level_for("ruost::task::keyboard::layout"), LevelFilter::Trace);
+    assert_eq!(logger::level_for("ruost::taskbar"), LevelFilter::Info); // prefix has to end at a path separator
+    logger::clear_module_level("ruost::task");
+    logger::clear_module_level("ruost::task::keyboard");
+    assert_eq!(logger::level_for("ruost::task::mouse"), LevelFilter::Info);
+}
+
+#[test_case]
+fn records_end_up_in_dmesg() {
+    log::info!("kept for dmesg");
+    log::debug!("filtered out");
+    let dmesg = logger::dmesg();
+    assert!(dmesg.lines().any(|line| line.contains("INFO") && line.ends_with("logger: kept for dmesg")));
+    assert!(!dmesg.contains("filtered out"));
+}
+
+#[test_case]
+fn sink_levels_apply_per_sink() {
+    logger::set_sink_level(Sink::Memory, LevelFilter::Error);
+    log::warn!("not in memory");
+    assert_eq!(logger::sink_level(Sink::Memory), LevelFilter::Error);
+    assert!(!logger::dmesg().contains("not in memory"));
+    logger::set_sink_level(Sink::Memory, LevelFilter::Trace);
+}
+
+#[test_case]
+fn logging_with_interrupts_disabled() {
+    x86_64::instructions::interrupts::without_interrupts(|| log::warn!("from a critical section"));
+    assert!(logger::dmesg().contains("from a critical section"));
+}
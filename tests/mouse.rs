@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use ruost::task::mouse::{self, MouseButtons, MouseEvent, MouseKind, PacketDecoder};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    ruost::init();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+fn decode(decoder: &mut PacketDecoder, bytes: &[u8]) -> Option<MouseEvent> {
+    let (last, rest) = bytes.split_last().unwrap();
+    for &byte in rest {
+        assert_eq!(decoder.add_byte(byte), None);
+    }
+    decoder.add_byte(*last)
+}
+
+#[test_case]
+fn standard_packet() {
+    let mut decoder = PacketDecoder::new(MouseKind::Standard);
+    let event = decode(&mut decoder, &[0b0000_1001, 5, 3]).expect("complete packet");
+    assert_eq!((event.dx, event.dy, event.wheel), (5, 3, 0));
+    assert_eq!(event.buttons, MouseButtons { left: true, right: false, middle: false });
+}
+
+#[test_case]
+fn negative_movement() {
+    let mut decoder = PacketDecoder::new(MouseKind::Standard);
+    let event = decode(&mut decoder, &[0b0011_1000, 0xfe, 0x80]).expect("complete packet");
+    assert_eq!((event.dx, event.dy), (-2, -128));
+}
+
+#[test_case]
+fn overflow_drops_movement() {
+    let mut decoder = PacketDecoder::new(MouseKind::Standard);
+    let event = decode(&mut decoder, &[0b0100_1010, 0xff, 7]).expect("complete packet");
+    assert_eq!((event.dx, event.dy), (0, 7));
+    assert!(event.buttons.right);
+}
+
+#[test_case]
+fn wheel_packet() {
+    let mut decoder = PacketDecoder::new(MouseKind::Wheel);
+    assert_eq!(decode(&mut decoder, &[0b0000_1100, 0, 0]), None); // still missing the wheel byte
+    let event = decoder.add_byte(0xff).expect("complete packet");
+    assert_eq!(event.wheel, -1);
+    assert!(event.buttons.middle);
+}
+
+#[test_case]
+fn resynchronizes_on_bad_first_byte() {
+    let mut decoder = PacketDecoder::new(MouseKind::Standard);
+    assert_eq!(decoder.add_byte(0x00), None); // bit 3 clear, can't be the first byte
+    let event = decode(&mut decoder, &[0b0000_1000, 1, 1]).expect("complete packet");
+    assert_eq!((event.dx, event.dy), (1, 1));
+}
+
+#[test_case]
+fn init_finds_mouse() {
+    let kind = mouse::init().expect("QEMU emulates a PS/2 mouse");
+    assert_eq!(kind, MouseKind::Wheel); // IntelliMouse
+}
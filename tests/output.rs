@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::output::{self, Target};
+use ruost::vga_buffer::{self, BUFFER_WIDTH};
+use ruost::{allocator, memory, println, serial_println, thread};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    thread::init(); // tests run on the boot thread, next to the flusher
+    output::start_flusher();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+// text 'above' rows over the cursor, straight from VGA memory
+fn row_above_cursor(above: usize) -> [u8; BUFFER_WIDTH] {
+    let row = vga_buffer::cursor().0 - above;
+    let cells = (0xb8000 + row * BUFFER_WIDTH * 2) as *const u16;
+    core::array::from_fn(|column| unsafe { cells.add(column).read_volatile() as u8 })
+}
+
+fn starts_with(row: [u8; BUFFER_WIDTH], text: &str) -> bool {
+    row.starts_with(text.as_bytes())
+}
+
+// prints while being formatted, like an exception handler hitting in the middle of a print
+struct PrintsWhenFormatted;
+
+impl fmt::Display for PrintsWhenFormatted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        println!("nested vga");
+        serial_println!("nested serial");
+        write!(f, "outer")
+    }
+}
+
+#[test_case]
+fn flusher_thread_writes_buffered_output() {
+    output::flush_blocking();
+    // the flusher can't run on this CPU while interrupts are off, so the line stays in the buffer
+    interrupts::without_interrupts(|| {
+        println!("buffered line");
+        assert!(!starts_with(row_above_cursor(1), "buffered line"));
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert!(starts_with(row_above_cursor(1), "buffered line"));
+}
+
+#[test_case]
+fn print_inside_print() {
+    println!("{}", PrintsWhenFormatted);
+    output::flush_blocking();
+    // the nested print goes around the buffer, so it comes out first
+    assert!(starts_with(row_above_cursor(2), "nested vga"));
+    assert!(starts_with(row_above_cursor(1), "outer"));
+    serial_println!("{}", PrintsWhenFormatted);
+    output::flush_blocking();
+    assert!(starts_with(row_above_cursor(1), "nested vga"));
+}
+
+#[test_case]
+fn overflow_is_reported() {
+    output::flush_blocking();
+    interrupts::without_interrupts(|| {
+        for _ in 0..100 {
+            println!("{:063}", 0); // 64 bytes with the newline, 6400 in total
+        }
+    });
+    output::flush_blocking();
+    assert!(starts_with(row_above_cursor(1), "[2304 bytes of output dropped]")); // 4096 byte buffer
+    assert!(starts_with(row_above_cursor(2), "000"));
+}
+
+#[test_case]
+fn printing_with_interrupts_disabled() {
+    interrupts::without_interrupts(|| {
+        println!("interrupts disabled");
+        output::write(Target::Serial, format_args!("written with interrupts disabled\n"));
+        output::flush_blocking(); // the flusher might have been preempted in the middle of a drain
+    });
+    assert!(starts_with(row_above_cursor(1), "interrupts disabled"));
+}
+
+#[test_case]
+fn clear_comes_after_earlier_prints() {
+    interrupts::without_interrupts(|| {
+        println!("before clear"); // still buffered, the flusher can't run
+        vga_buffer::clear();
+    });
+    assert_eq!(vga_buffer::cursor(), (0, 0));
+    assert!(!starts_with(row_above_cursor(0), "before clear"));
+    vga_buffer::write_at(0, 0, "written at");
+    println!("after clear");
+    output::flush_blocking();
+    assert!(starts_with(row_above_cursor(1), "after clear")); // prints after write_at do go over it
+}
@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ruost::memory::{self, fault::{self, FaultResolution, PageFault, RegionKind}, PAGE_SIZE};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+const WRITABLE: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+fn reserve(pages: usize) -> VirtAddr {
+    memory::with_vmm(|vmm| vmm.reserve(pages * PAGE_SIZE)).unwrap()
+}
+
+#[test_case]
+fn lazy_region_is_mapped_on_access() {
+    let start = reserve(4);
+    fault::register_region(start, 4 * PAGE_SIZE, RegionKind::Lazy(WRITABLE), "test lazy").unwrap();
+    assert!(memory::with_vmm(|vmm| vmm.translate(start)).is_none());
+
+    let ptr: *mut u64 = (start + 2 * PAGE_SIZE as u64).as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0); // fresh pages are zeroed
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    // only the touched page got a frame
+    assert!(memory::with_vmm(|vmm| vmm.translate(start)).is_none());
+    assert!(memory::with_vmm(|vmm| vmm.translate(start + 2 * PAGE_SIZE as u64)).is_some());
+}
+
+#[test_case]
+fn copy_on_write_region_copies_frame_on_write() {
+    let start = memory::with_vmm(|vmm| vmm.allocate_region(PAGE_SIZE, WRITABLE)).unwrap();
+    let copy = reserve(1);
+    let ptr: *mut u64 = start.as_mut_ptr();
+    let copy_ptr: *mut u64 = copy.as_mut_ptr();
+    unsafe { ptr.write_volatile(7) };
+    let old_frame = memory::with_vmm(|vmm| {
+        vmm.share_page(start, copy, PageTableFlags::PRESENT).unwrap();
+        vmm.translate(start).unwrap()
+    });
+    fault::register_region(start, PAGE_SIZE, RegionKind::CopyOnWrite(WRITABLE), "test cow").unwrap();
+    fault::register_region(copy, PAGE_SIZE, RegionKind::CopyOnWrite(WRITABLE), "test cow copy").unwrap();
+
+    unsafe {
+        ptr.write_volatile(ptr.read_volatile() + 1);
+        assert_eq!(ptr.read_volatile(), 8);
+    }
+    let new_frame = memory::with_vmm(|vmm| vmm.translate(start)).unwrap();
+    assert_ne!(old_frame, new_frame);
+    // the other mapping still sees the original content
+    assert_eq!(unsafe { copy_ptr.read_volatile() }, 7);
+
+    // it's the last mapping of the old frame now, writing to it takes the frame over instead of copying it
+    let free_frames = memory::with_vmm(|vmm| vmm.free_frames());
+    unsafe { copy_ptr.write_volatile(9) };
+    assert_eq!(memory::with_vmm(|vmm| vmm.translate(copy)), Some(old_frame));
+    assert_eq!(memory::with_vmm(|vmm| vmm.free_frames()), free_frames);
+    assert_eq!(unsafe { ptr.read_volatile() }, 8);
+}
+
+#[test_case]
+fn unmapping_shared_frame_keeps_it_for_other_mapping() {
+    let start = memory::with_vmm(|vmm| vmm.allocate_region(PAGE_SIZE, WRITABLE)).unwrap();
+    let copy = reserve(1);
+    unsafe { start.as_mut_ptr::<u64>().write_volatile(5) };
+    let free_frames = memory::with_vmm(|vmm| {
+        vmm.share_page(start, copy, PageTableFlags::PRESENT).unwrap();
+        vmm.free_frames()
+    });
+
+    memory::with_vmm(|vmm| vmm.unmap_region(start, PAGE_SIZE)).unwrap();
+    assert_eq!(memory::with_vmm(|vmm| vmm.free_frames()), free_frames);
+    assert_eq!(unsafe { copy.as_ptr::<u64>().read_volatile() }, 5);
+    memory::with_vmm(|vmm| vmm.unmap_region(copy, PAGE_SIZE)).unwrap();
+    assert_eq!(memory::with_vmm(|vmm| vmm.free_frames()), free_frames + 1);
+}
+
+static CUSTOM_FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+fn custom_handler(fault: &PageFault) -> FaultResolution {
+    CUSTOM_FAULTS.fetch_add(1, Ordering::SeqCst);
+    let page = fault.addr.align_down(PAGE_SIZE as u64);
+    match memory::with_vmm(|vmm| vmm.map_zeroed_page(page, WRITABLE)) {
+        Ok(()) => FaultResolution::Resolved,
+        Err(_) => FaultResolution::Fatal,
+    }
+}
+
+#[test_case]
+fn custom_handler_resolves_fault() {
+    let start = reserve(1);
+    fault::register_region(start, PAGE_SIZE, RegionKind::Custom(custom_handler), "test custom").unwrap();
+
+    let ptr: *mut u8 = start.as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(1);
+        ptr.write_volatile(2); // mapped now, no second fault
+    }
+    assert_eq!(CUSTOM_FAULTS.load(Ordering::SeqCst), 1);
+    assert_eq!(fault::unregister_region(start).map(|r| r.name), Some("test custom"));
+}
+
+#[test_case]
+fn overlapping_regions_are_rejected() {
+    let start = reserve(2);
+    fault::register_region(start, 2 * PAGE_SIZE, RegionKind::Guard, "test guard").unwrap();
+    let result = fault::register_region(start + PAGE_SIZE as u64, PAGE_SIZE, RegionKind::Guard, "test overlap");
+    assert_eq!(result, Err(fault::RegionError::Overlap));
+}
+
+#[test_case]
+fn zero_sized_regions_map_nothing() {
+    let start = reserve(1);
+    memory::with_vmm(|vmm| {
+        let free = vmm.free_frames();
+        vmm.map_region(start, 0, WRITABLE).unwrap();
+        assert!(vmm.translate(start).is_none());
+        vmm.protect(start, 0, PageTableFlags::PRESENT).unwrap();
+        vmm.unmap_region(start, 0).unwrap();
+        assert_eq!(vmm.free_frames(), free);
+    });
+}
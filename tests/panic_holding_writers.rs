@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use core::fmt;
+use core::panic::PanicInfo;
+use ruost::serial;
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::vga_buffer::{self, BUFFER_WIDTH};
+use ruost::{halt, output, println, serial_print, serial_println};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_holding_writers::panic_holding_writers...\t");
+    ruost::init();
+    // holds the VGA writer while formatting, which writes to the log port and panics while holding that too
+    vga_buffer::try_print(format_args!("{}", WritesToSerial));
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failure);
+    halt()
+}
+
+struct WritesToSerial;
+
+impl fmt::Display for WritesToSerial {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        let _ = serial::write_to(serial::log_port(), format_args!("{}", Panics));
+        Ok(())
+    }
+}
+
+struct Panics;
+
+impl fmt::Display for Panics {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        panic!("panicked holding the writers");
+    }
+}
+
+// without the writers unlocked by force, both prints here would fail (this CPU holds the locks)
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    output::panic_mode();
+    println!("{}", info);
+    let row = vga_buffer::cursor().0 - 1;
+    let cells = (0xb8000 + row * BUFFER_WIDTH * 2) as *const u16;
+    let message = b"panicked holding the writers"; // last line of the panic info
+    let shown = (0..message.len()).all(|column| unsafe { cells.add(column).read_volatile() } as u8 == message[column]);
+    match serial::write_to(serial::log_port(), format_args!("")) {
+        Ok(()) if shown => {
+            serial_println!("[ok]");
+            exit_qemu(QemuExitCode::Success);
+        }
+        result => {
+            serial_println!("[fail]\n");
+            serial_println!("Error: panic message shown: {}, log port: {:?}\n", shown, result.err());
+            exit_qemu(QemuExitCode::Failure);
+        }
+    }
+    halt()
+}
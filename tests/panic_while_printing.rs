@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use core::fmt;
+use core::panic::PanicInfo;
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{halt, output, println, serial_print, serial_println};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_while_printing::panic_while_printing...\t");
+    ruost::init();
+    println!("{}", PanicsWhenFormatted);
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failure);
+    halt()
+}
+
+struct PanicsWhenFormatted;
+
+impl fmt::Display for PanicsWhenFormatted {
+    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+        panic!("panicked while printing");
+    }
+}
+
+// the panic message has to come through although the panic hit in the middle of a print
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    output::panic_mode();
+    println!("{}", info);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    halt()
+}
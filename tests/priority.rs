@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use ruost::task::{executor::{Executor, Spawner}, yield_now, Priority, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{allocator, memory, serial_print, serial_println};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests(executor.spawner())));
+    executor.run();
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("higher_priority_runs_first", higher_priority_runs_first(&spawner)).await;
+    test("busy_task_does_not_starve_others", busy_task_does_not_starve_others(&spawner)).await;
+    test("low_priority_makes_progress", low_priority_makes_progress(&spawner)).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("priority::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+async fn higher_priority_runs_first(spawner: &Spawner) {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let handles: Vec<_> = [Priority::Low, Priority::Normal, Priority::High]
+        .into_iter()
+        .map(|priority| {
+            let order = order.clone();
+            spawner.spawn(Task::new(async move { order.lock().push(priority) }).with_priority(priority))
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    assert_eq!(*order.lock(), [Priority::High, Priority::Normal, Priority::Low]);
+}
+
+// keeps waking itself until stopped
+async fn spin_task(stop: Arc<AtomicBool>, polls: Arc<AtomicU64>) {
+    while !stop.load(Ordering::Relaxed) {
+        polls.fetch_add(1, Ordering::Relaxed);
+        yield_now().await;
+    }
+}
+
+async fn busy_task_does_not_starve_others(spawner: &Spawner) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let polls = Arc::new(AtomicU64::new(0));
+    let busy = spawner.spawn(Task::new(spin_task(stop.clone(), polls.clone())));
+
+    // same priority as the busy task, has to get its turn every round
+    for _ in 0..10 {
+        yield_now().await;
+    }
+    stop.store(true, Ordering::Relaxed);
+    busy.await.unwrap();
+    assert!(polls.load(Ordering::Relaxed) <= 10 * 4 + 4, "busy task wasn't limited by poll budget");
+}
+
+async fn low_priority_makes_progress(spawner: &Spawner) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let polls = Arc::new(AtomicU64::new(0));
+    let busy = spawner.spawn(Task::new(spin_task(stop.clone(), polls.clone())).with_priority(Priority::High));
+
+    // high priority queue is never empty now, low priority task only runs thanks to aging
+    let low = spawner.spawn(Task::new(async { 5 }).with_priority(Priority::Low));
+    assert_eq!(low.await, Ok(5));
+    stop.store(true, Ordering::Relaxed);
+    busy.await.unwrap();
+}
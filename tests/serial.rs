@@ -0,0 +1,137 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ruost::serial::{self, line, ComPort, LineDiscipline, Parity, UartConfig, UartError};
+use ruost::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+// feeds 'input', returns completed lines and everything echoed
+fn feed(discipline: &mut LineDiscipline, input: &[u8]) -> (Vec<String>, String) {
+    let mut echo = String::new();
+    let lines = input.iter().filter_map(|&byte| discipline.push(byte, &mut echo)).collect();
+    (lines, echo)
+}
+
+#[test_case]
+fn lines_end_with_cr_lf_or_both() {
+    let mut discipline = LineDiscipline::new();
+    let (lines, _) = feed(&mut discipline, b"one\rtwo\nthree\r\nfour");
+    assert_eq!(lines, ["one", "two", "three"]);
+    assert_eq!(discipline.pending(), b"four");
+}
+
+#[test_case]
+fn input_is_echoed() {
+    let mut discipline = LineDiscipline::new();
+    let (_, echo) = feed(&mut discipline, b"hi\r");
+    assert_eq!(echo, "hi\r\n");
+}
+
+#[test_case]
+fn backspace_erases_last_character() {
+    let mut discipline = LineDiscipline::new();
+    let (lines, echo) = feed(&mut discipline, b"ab\x7fc\x08\x08\x08d\n");
+    assert_eq!(lines, ["d"]);
+    assert_eq!(echo, "ab\x08 \x08c\x08 \x08\x08 \x08d\r\n"); // nothing to erase for the third backspace
+}
+
+#[test_case]
+fn backspace_erases_whole_utf8_character() {
+    let mut discipline = LineDiscipline::new();
+    let (lines, echo) = feed(&mut discipline, "zaż\x7f\n".as_bytes());
+    assert_eq!(lines, ["za"]);
+    assert_eq!(echo, "zaż\x08 \x08\r\n");
+}
+
+#[test_case]
+fn kill_line() {
+    let mut discipline = LineDiscipline::new();
+    let (lines, _) = feed(&mut discipline, b"wrong\x15right\r");
+    assert_eq!(lines, ["right"]);
+}
+
+#[test_case]
+fn echo_can_be_disabled() {
+    let mut discipline = LineDiscipline::new();
+    discipline.set_echo(false);
+    let (lines, echo) = feed(&mut discipline, b"secret\r");
+    assert_eq!(lines, ["secret"]);
+    assert!(echo.is_empty());
+}
+
+#[test_case]
+fn overlong_line_is_cut() {
+    let mut discipline = LineDiscipline::new();
+    let input: Vec<u8> = core::iter::repeat_n(b'x', line::MAX_LINE + 10).collect();
+    let (lines, echo) = feed(&mut discipline, &input);
+    assert!(lines.is_empty());
+    assert_eq!(discipline.pending().len(), line::MAX_LINE);
+    assert!(echo.ends_with(&"\x07".repeat(10))); // bell for each dropped byte
+    let (lines, _) = feed(&mut discipline, b"\r");
+    assert_eq!(lines[0].len(), line::MAX_LINE);
+}
+
+#[test_case]
+fn character_at_the_limit_is_not_split() {
+    let mut discipline = LineDiscipline::new();
+    let input: Vec<u8> = core::iter::repeat_n(b'x', line::MAX_LINE - 1).collect();
+    feed(&mut discipline, &input);
+    let (_, echo) = feed(&mut discipline, "ą".as_bytes());
+    assert_eq!(echo, "\x07"); // one bell for the whole character
+    assert_eq!(discipline.pending().len(), line::MAX_LINE - 1);
+    let (_, echo) = feed(&mut discipline, b"y");
+    assert_eq!(echo, "y");
+    let (lines, _) = feed(&mut discipline, b"\r");
+    assert!(lines[0].ends_with("xy"));
+}
+
+#[test_case]
+fn com1_is_detected() {
+    assert!(serial::is_present(ComPort::Com1)); // test output goes through it
+    assert_eq!(serial::log_port(), ComPort::Com1);
+}
+
+#[test_case]
+fn missing_port_is_reported() {
+    assert!(!serial::is_present(ComPort::Com4)); // test runs attach a single serial port
+    assert_eq!(serial::set_log_port(ComPort::Com4), Err(UartError::NotPresent));
+    assert_eq!(serial::log_port(), ComPort::Com1);
+}
+
+#[test_case]
+fn port_can_be_reconfigured() {
+    let config = UartConfig { baud_rate: 115_200, parity: Parity::Even, ..UartConfig::default() };
+    serial::configure(ComPort::Com1, config).unwrap();
+    assert_eq!(serial::config(ComPort::Com1), Ok(config));
+    serial::configure(ComPort::Com1, UartConfig::default()).unwrap();
+}
+
+#[test_case]
+fn invalid_baud_rate_is_rejected() {
+    let config = UartConfig { baud_rate: 12_345, ..UartConfig::default() };
+    assert_eq!(serial::configure(ComPort::Com1, config), Err(UartError::InvalidBaudRate(12_345)));
+    assert_eq!(serial::config(ComPort::Com1), Ok(UartConfig::default()));
+}
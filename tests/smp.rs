@@ -0,0 +1,177 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{collections::BTreeSet, rc::Rc, sync::Arc, vec, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::cell::Cell;
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::interrupts::apic;
+use ruost::smp::{self, percpu};
+use ruost::task::executor::{Executor, LocalSpawner, Spawner};
+use ruost::task::{yield_now, LocalTask, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::time::Instant;
+use ruost::memory::{fault::{self, RegionKind}, PAGE_SIZE};
+use ruost::{acpi, allocator, interrupts, memory, serial_print, serial_println};
+use x86_64::structures::paging::PageTableFlags;
+use spin::Mutex;
+
+entry_point!(main);
+
+// meant to be run with '-smp 4' (see test-args), passes with a single CPU too
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    acpi::init();
+    interrupts::init_apic();
+
+    serial_print!("smp::all_processors_started...\t");
+    let cpus = smp::init().unwrap_or(1);
+    if let Some(info) = acpi::info() {
+        let enabled = info.processors.iter().filter(|processor| processor.enabled).count();
+        assert_eq!(cpus, enabled.max(1));
+    }
+    serial_println!("[ok]");
+
+    let mut executor = Executor::new();
+    smp::start_workers(&executor.spawner());
+    executor.spawn_local(LocalTask::new(run_tests(executor.spawner(), executor.local_spawner())));
+    executor.run();
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+// local task itself, so it stays on the bootstrap processor
+async fn run_tests(spawner: Spawner, local_spawner: LocalSpawner) {
+    test("per_cpu_data", per_cpu_data(&spawner)).await;
+    test("work_is_spread_across_cpus", work_is_spread_across_cpus(&spawner)).await;
+    test("spawn_local_stays_on_its_cpu", spawn_local_stays_on_its_cpu(&local_spawner)).await;
+    test("send_tasks_return_output", send_tasks_return_output(&spawner)).await;
+    test("lazy_faults_while_heap_grows", lazy_faults_while_heap_grows(&spawner)).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("smp::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+fn busy_wait(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        core::hint::spin_loop();
+    }
+}
+
+async fn per_cpu_data(spawner: &Spawner) {
+    assert_eq!(smp::cpu_id(), 0);
+    let handles: Vec<_> = (0..smp::cpu_count() * 4)
+        .map(|_| spawner.spawn(Task::new(async {
+            busy_wait(Duration::from_millis(5));
+            let cpu = percpu::current().expect("per-CPU data missing");
+            // local APIC id register tells which processor really runs this
+            assert_eq!(cpu.apic_id, apic::local_apic().unwrap().id());
+            cpu.id
+        })))
+        .collect();
+    for handle in handles {
+        assert!(handle.await.unwrap() < smp::cpu_count());
+    }
+}
+
+async fn work_is_spread_across_cpus(spawner: &Spawner) {
+    let cpus = Arc::new(Mutex::new(BTreeSet::new()));
+    let handles: Vec<_> = (0..smp::cpu_count() * 8)
+        .map(|_| {
+            let cpus = cpus.clone();
+            spawner.spawn(Task::new(async move {
+                for _ in 0..4 {
+                    busy_wait(Duration::from_millis(5));
+                    cpus.lock().insert(smp::cpu_id());
+                    yield_now().await;
+                }
+            }))
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    if smp::cpu_count() > 1 {
+        assert!(cpus.lock().len() > 1, "no task was stolen by another CPU");
+    }
+}
+
+async fn spawn_local_stays_on_its_cpu(local_spawner: &LocalSpawner) {
+    let polls = Rc::new(Cell::new(0)); // not Send, couldn't be used by a Task
+    let counter = polls.clone();
+    let handle = local_spawner.spawn_local(LocalTask::new(async move {
+        for _ in 0..20 {
+            assert_eq!(smp::cpu_id(), 0);
+            counter.set(counter.get() + 1);
+            yield_now().await;
+        }
+    }));
+    handle.await.unwrap();
+    assert_eq!(polls.get(), 20);
+}
+
+async fn send_tasks_return_output(spawner: &Spawner) {
+    let handles: Vec<_> = (0..500u64)
+        .map(|i| spawner.spawn(Task::new(async move {
+            yield_now().await;
+            i * 2
+        })))
+        .collect();
+    let mut sum = 0;
+    for handle in handles {
+        sum += handle.await.unwrap();
+    }
+    assert_eq!(sum, (0..500).map(|i| i * 2).sum());
+}
+
+// faults of several CPUs (some on the same page) are resolved while the heap growing on this one holds the VMM
+async fn lazy_faults_while_heap_grows(spawner: &Spawner) {
+    const PAGES: usize = 64;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let start = memory::with_vmm(|vmm| vmm.reserve(PAGES * PAGE_SIZE)).unwrap();
+    fault::register_region(start, PAGES * PAGE_SIZE, RegionKind::Lazy(flags), "smp lazy").unwrap();
+    let base = start.as_u64();
+
+    let tasks = smp::cpu_count() * 4;
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| spawner.spawn(Task::new(async move {
+            for page in 0..PAGES { // every task touches every page, each at its own offset
+                let ptr = (base + (page * PAGE_SIZE + task * 8) as u64) as *mut u64;
+                unsafe { ptr.write_volatile(task as u64 + 1) };
+            }
+        })))
+        .collect();
+
+    let heap_before = allocator::heap_size();
+    let mut blocks = Vec::new();
+    while allocator::heap_size() < heap_before * 4 {
+        blocks.push(vec![0u8; 16 * 1024]);
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    for page in 0..PAGES {
+        for task in 0..tasks {
+            let ptr = (base + (page * PAGE_SIZE + task * 8) as u64) as *const u64;
+            assert_eq!(unsafe { ptr.read_volatile() }, task as u64 + 1); // nobody got a second, zeroed frame
+        }
+    }
+    assert!(fault::unregister_region(start).is_some());
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::task::{executor::{Executor, Spawner}, join::JoinError, timer, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{allocator, memory, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let forever = executor.spawn(Task::new(core::future::pending::<()>()));
+    executor.spawn(Task::new(run_tests(spawner)));
+    executor.run(); // returns after run_tests requests shutdown
+
+    serial_print!("spawner::shutdown_cancels_remaining_tasks...\t");
+    assert!(forever.is_finished());
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("join_handle_returns_output", join_handle_returns_output(&spawner)).await;
+    test("spawned_tasks_run_concurrently", spawned_tasks_run_concurrently(&spawner)).await;
+    test("nested_spawns", nested_spawns(&spawner)).await;
+    test("spawn_more_than_queue_capacity", spawn_more_than_queue_capacity(&spawner)).await;
+    test("detached_task_keeps_running", detached_task_keeps_running(&spawner)).await;
+    test("abort_cancels_task", abort_cancels_task(&spawner)).await;
+    test("abort_after_finish_keeps_output", abort_after_finish_keeps_output(&spawner)).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("spawner::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+async fn join_handle_returns_output(spawner: &Spawner) {
+    let handle = spawner.spawn(Task::new(async { String::from("done") }));
+    assert_eq!(handle.await.unwrap(), "done");
+}
+
+async fn spawned_tasks_run_concurrently(spawner: &Spawner) {
+    // both sleep 20ms, sequential execution would take 40ms
+    let start = ruost::time::Instant::now();
+    let a = spawner.spawn(Task::new(timer::sleep(Duration::from_millis(20))));
+    let b = spawner.spawn(Task::new(timer::sleep(Duration::from_millis(20))));
+    a.await.unwrap();
+    b.await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(35));
+}
+
+async fn nested_spawns(spawner: &Spawner) {
+    let inner_spawner = spawner.clone(); // tasks get their own handle
+    let outer = spawner.spawn(Task::new(async move {
+        let inner = inner_spawner.spawn(Task::new(async { 21 }));
+        inner.await.unwrap() * 2
+    }));
+    assert_eq!(outer.await, Ok(42));
+}
+
+async fn spawn_more_than_queue_capacity(spawner: &Spawner) {
+    let handles: Vec<_> = (0..500u64).map(|i| spawner.spawn(Task::new(async move { i }))).collect();
+    let mut sum = 0;
+    for handle in handles {
+        sum += handle.await.unwrap();
+    }
+    assert_eq!(sum, (0..500).sum());
+}
+
+async fn detached_task_keeps_running(spawner: &Spawner) {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    drop(spawner.spawn(Task::new(async { RAN.store(true, Ordering::SeqCst) })));
+    timer::sleep(Duration::from_millis(5)).await;
+    assert!(RAN.load(Ordering::SeqCst));
+}
+
+async fn abort_cancels_task(spawner: &Spawner) {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct SetOnDrop;
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let handle = spawner.spawn(Task::new(async {
+        let _guard = SetOnDrop;
+        timer::sleep(Duration::from_secs(60)).await;
+    }));
+    timer::sleep(Duration::from_millis(2)).await; // let it start sleeping
+    handle.abort();
+    assert_eq!(handle.await, Err(JoinError::Cancelled));
+    assert!(DROPPED.load(Ordering::SeqCst)); // future was dropped, not just forgotten
+}
+
+async fn abort_after_finish_keeps_output(spawner: &Spawner) {
+    let handle = spawner.spawn(Task::new(async { 7 }));
+    timer::sleep(Duration::from_millis(2)).await;
+    assert!(handle.is_finished());
+    handle.abort();
+    assert_eq!(handle.await, Ok(7));
+}
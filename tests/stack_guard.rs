@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use ruost::memory::{self, stack};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{halt, serial_print, serial_println};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(ruost::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt
+    };
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_guard::overflow_hits_guard_page...\t");
+
+    ruost::gdt::init();
+    TEST_IDT.load();
+    memory::init(boot_info);
+    ruost::gdt::init_stacks(); // double fault handler runs on a guard-paged IST stack too
+
+    let stack = stack::allocate_stack(4).expect("stack allocation failed");
+    GUARD_PAGE.store(stack.guard_page().as_u64(), Ordering::SeqCst);
+
+    // switch to the new stack and overflow it there
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "call {entry}",
+            top = in(reg) stack.top().as_u64(),
+            entry = sym overflow_entry,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn overflow_entry() -> ! {
+    stack_overflow();
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+// page fault on the guard page can't push its frame on the overflowed stack, so CPU escalates to double fault
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let guard = GUARD_PAGE.load(Ordering::SeqCst);
+    let fault_addr = Cr2::read().as_u64();
+    if (guard..guard + memory::PAGE_SIZE as u64).contains(&fault_addr) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[fail]\nfault at {:#x}, guard page at {:#x}", fault_addr, guard);
+        exit_qemu(QemuExitCode::Failure);
+    }
+    halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
@@ -0,0 +1,209 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use conquer_once::spin::OnceCell;
+use core::arch::asm;
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::interrupts::{self, irq};
+use ruost::task::sync::{broadcast, mpsc, oneshot, Mutex, Notify, RwLock, Semaphore};
+use ruost::task::{executor::{Executor, Spawner}, timer, yield_now, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{allocator, memory, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests(executor.spawner())));
+    executor.run();
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("mutex_serializes_across_await", mutex_serializes_across_await(&spawner)).await;
+    test("rwlock_readers_share_writer_excludes", rwlock_readers_share_writer_excludes(&spawner)).await;
+    test("semaphore_limits_concurrency", semaphore_limits_concurrency(&spawner)).await;
+    test("notify_stores_one_permit", notify_stores_one_permit()).await;
+    test("notify_waiters_wakes_everyone", notify_waiters_wakes_everyone(&spawner)).await;
+    test("oneshot_delivers_or_closes", oneshot_delivers_or_closes(&spawner)).await;
+    test("mpsc_applies_backpressure", mpsc_applies_backpressure(&spawner)).await;
+    test("broadcast_reaches_every_receiver", broadcast_reaches_every_receiver()).await;
+    test("broadcast_reports_lag", broadcast_reports_lag()).await;
+    test("signalled_from_interrupt_handler", signalled_from_interrupt_handler()).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("sync::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+async fn mutex_serializes_across_await(spawner: &Spawner) {
+    let counter = Arc::new(Mutex::new(0));
+    let handles: Vec<_> = (0..10)
+        .map(|_| {
+            let counter = counter.clone();
+            spawner.spawn(Task::new(async move {
+                let mut guard = counter.lock().await;
+                let value = *guard;
+                yield_now().await; // others run meanwhile, but can't get the lock
+                *guard = value + 1;
+            }))
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    assert_eq!(*counter.lock().await, 10);
+}
+
+async fn rwlock_readers_share_writer_excludes(spawner: &Spawner) {
+    let lock = Arc::new(RwLock::new(1));
+    let first = lock.read().await;
+    let second = lock.try_read().expect("readers share the lock");
+    assert!(lock.try_write().is_none());
+
+    let writer_lock = lock.clone();
+    let writer = spawner.spawn(Task::new(async move { *writer_lock.write().await = 2 }));
+    yield_now().await;
+    assert!(lock.try_read().is_none()); // writer is waiting, new readers queue behind it
+    drop((first, second));
+    writer.await.unwrap();
+    assert_eq!(*lock.read().await, 2);
+}
+
+async fn semaphore_limits_concurrency(spawner: &Spawner) {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    let semaphore = Arc::new(Semaphore::new(3));
+    let handles: Vec<_> = (0..10)
+        .map(|_| {
+            let semaphore = semaphore.clone();
+            spawner.spawn(Task::new(async move {
+                let _permit = semaphore.acquire().await;
+                let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+                MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+                timer::sleep(Duration::from_millis(2)).await;
+                RUNNING.fetch_sub(1, Ordering::SeqCst);
+            }))
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 3);
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+async fn notify_stores_one_permit() {
+    let notify = Notify::new();
+    notify.notify_one();
+    notify.notify_one(); // permits don't add up
+    notify.notified().await;
+    let result = timer::timeout(notify.notified(), Duration::from_millis(5)).await;
+    assert!(result.is_err());
+}
+
+async fn notify_waiters_wakes_everyone(spawner: &Spawner) {
+    let notify = Arc::new(Notify::new());
+    let handles: Vec<_> = (0..5)
+        .map(|_| {
+            let notify = notify.clone();
+            spawner.spawn(Task::new(async move { notify.notified().await }))
+        })
+        .collect();
+    yield_now().await; // let all of them start waiting
+    notify.notify_waiters();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+async fn oneshot_delivers_or_closes(spawner: &Spawner) {
+    let (sender, receiver) = oneshot::channel();
+    spawner.spawn(Task::new(async move { sender.send(5).unwrap() }));
+    assert_eq!(receiver.await, Ok(5));
+
+    let (sender, receiver) = oneshot::channel::<u32>();
+    drop(sender);
+    assert_eq!(receiver.await, Err(oneshot::RecvError));
+}
+
+async fn mpsc_applies_backpressure(spawner: &Spawner) {
+    let (sender, mut receiver) = mpsc::channel(2);
+    let producer = spawner.spawn(Task::new(async move {
+        for i in 0..20 {
+            sender.send(i).await.unwrap(); // waits whenever the 2 slots are taken
+        }
+    }));
+
+    let mut received = Vec::new();
+    while let Some(value) = receiver.recv().await {
+        received.push(value);
+    }
+    producer.await.unwrap();
+    assert_eq!(received, (0..20).collect::<Vec<_>>());
+}
+
+async fn broadcast_reaches_every_receiver() {
+    let (sender, mut first) = broadcast::channel(4);
+    let mut second = sender.subscribe();
+    sender.send(1).unwrap();
+    sender.send(2).unwrap();
+    assert_eq!(first.recv().await, Ok(1));
+    assert_eq!(first.recv().await, Ok(2));
+    assert_eq!(second.recv().await, Ok(1));
+    drop(sender);
+    assert_eq!(second.recv().await, Ok(2));
+    assert_eq!(second.recv().await, Err(broadcast::RecvError::Closed));
+}
+
+async fn broadcast_reports_lag() {
+    let (sender, mut receiver) = broadcast::channel(2);
+    for i in 0..5 {
+        sender.send(i).unwrap();
+    }
+    assert_eq!(receiver.recv().await, Err(broadcast::RecvError::Lagged(3)));
+    assert_eq!(receiver.recv().await, Ok(3));
+}
+
+static IRQ_NOTIFY: Notify = Notify::new();
+static IRQ_SEMAPHORE: Semaphore = Semaphore::new(0);
+static IRQ_SENDER: OnceCell<mpsc::Sender<u8>> = OnceCell::uninit();
+
+fn irq5_handler() {
+    IRQ_NOTIFY.notify_one();
+    IRQ_SEMAPHORE.add_permits(1);
+    let _ = IRQ_SENDER.get().unwrap().try_send(5);
+}
+
+async fn signalled_from_interrupt_handler() {
+    let (sender, mut receiver) = mpsc::channel(4);
+    IRQ_SENDER.init_once(|| sender);
+    interrupts::register_irq(5, irq5_handler).unwrap();
+
+    unsafe { asm!("int {}", const irq::vector(5)) };
+    IRQ_NOTIFY.notified().await;
+    IRQ_SEMAPHORE.acquire().await.forget();
+    assert_eq!(receiver.recv().await, Some(5));
+    interrupts::unregister_irq(5);
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use ruost::task::executor::{Executor, Spawner, TaskInfo, TaskState};
+use ruost::task::{yield_now, Priority, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{allocator, memory, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests(executor.spawner())).with_name("tests"));
+    executor.run();
+    assert!(executor.snapshot().is_empty(), "shutdown left tasks in the registry");
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("snapshot_lists_running_task", snapshot_lists_running_task(&spawner)).await;
+    test("counts_polls", counts_polls(&spawner)).await;
+    test("waiting_task_state", waiting_task_state(&spawner)).await;
+    test("finished_task_is_removed", finished_task_is_removed(&spawner)).await;
+    test("display_row", display_row()).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("task_stats::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+fn find(spawner: &Spawner, name: &str) -> Option<TaskInfo> {
+    spawner.snapshot().into_iter().find(|info| info.name.as_deref() == Some(name))
+}
+
+async fn snapshot_lists_running_task(spawner: &Spawner) {
+    let info = find(spawner, "tests").expect("test task missing from snapshot");
+    assert_eq!(info.state, TaskState::Running);
+    assert_eq!(info.priority, Priority::Normal);
+    assert!(info.polls >= 1);
+}
+
+async fn counts_polls(spawner: &Spawner) {
+    let handle = spawner.spawn(Task::new(async {
+        for _ in 0..5 {
+            yield_now().await;
+        }
+    }).with_name("yielder").with_priority(Priority::Low));
+    yield_now().await; // lets the executor pick it up
+
+    let info = find(spawner, "yielder").expect("spawned task missing from snapshot");
+    assert_eq!(info.priority, Priority::Low);
+    assert_eq!(info.state, TaskState::Ready);
+    handle.await.unwrap();
+
+    let info = find(spawner, "tests").unwrap();
+    assert!(info.last_polled.is_some());
+    assert!(info.last_woken.is_some(), "yield_now wakes the task");
+}
+
+async fn waiting_task_state(spawner: &Spawner) {
+    // never woken, until aborted
+    let handle = spawner.spawn(Task::new(core::future::pending::<()>()).with_name("sleeper"));
+    for _ in 0..3 {
+        yield_now().await;
+    }
+
+    let info = find(spawner, "sleeper").unwrap();
+    assert_eq!(info.state, TaskState::Waiting);
+    assert_eq!(info.polls, 1);
+    handle.abort();
+    assert!(handle.await.is_err());
+}
+
+async fn finished_task_is_removed(spawner: &Spawner) {
+    spawner.spawn(Task::new(async {}).with_name("short")).await.unwrap();
+    yield_now().await;
+    assert!(find(spawner, "short").is_none());
+}
+
+async fn display_row() {
+    let info = TaskInfo {
+        id: 7,
+        name: Some("shell".into()),
+        priority: Priority::High,
+        state: TaskState::Waiting,
+        polls: 42,
+        poll_time: core::time::Duration::from_micros(1500),
+        spawned_at: ruost::time::Instant::from_nanos(0),
+        last_woken: None,
+        last_polled: None,
+    };
+    let row = format!("{}", info);
+    assert_eq!(row.len(), TaskInfo::HEADER.len());
+    assert!(row.contains("shell") && row.contains("High") && row.contains("Waiting") && row.contains("1500"));
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use ruost::time::Instant;
+use ruost::{allocator, memory, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+    thread::init(); // tests run on the boot thread
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+#[test_case]
+fn join_returns_value() {
+    let handle = thread::spawn(|| 6 * 7);
+    assert_eq!(handle.join(), 42);
+}
+
+#[test_case]
+fn threads_have_distinct_ids() {
+    let main = thread::current_id().expect("thread::init ran");
+    let handle = thread::spawn(thread::current_id);
+    let id = handle.id();
+    assert_ne!(id, main);
+    assert_eq!(handle.join(), Some(id));
+}
+
+#[test_case]
+fn spinning_thread_is_preempted() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static SPINS: AtomicU64 = AtomicU64::new(0);
+
+    let spinner = thread::spawn(|| {
+        while !STOP.load(Ordering::Acquire) { // never yields, only the timer gets this thread off the CPU
+            SPINS.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    thread::sleep(Duration::from_millis(30));
+    assert!(SPINS.load(Ordering::Relaxed) > 0);
+    assert!(!spinner.is_finished());
+    STOP.store(true, Ordering::Release);
+    spinner.join();
+}
+
+#[test_case]
+fn sleep_takes_at_least_its_duration() {
+    let start = Instant::now();
+    thread::sleep(Duration::from_millis(20));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(20) && elapsed < Duration::from_millis(100), "{:?}", elapsed);
+}
+
+#[test_case]
+fn join_waits_for_sleeping_thread() {
+    let handle = thread::spawn(|| {
+        thread::sleep(Duration::from_millis(10));
+        "done"
+    });
+    assert!(!handle.is_finished());
+    assert_eq!(handle.join(), "done");
+}
+
+#[test_case]
+fn yield_lets_other_threads_run() {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let handle = thread::spawn(|| COUNTER.fetch_add(1, Ordering::AcqRel));
+    while COUNTER.load(Ordering::Acquire) == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(handle.join(), 0);
+}
+
+#[test_case]
+fn many_threads() {
+    let handles: Vec<_> = (0..32u64).map(|i| thread::spawn(move || i * i)).collect();
+    let sum: u64 = handles.into_iter().map(thread::JoinHandle::join).sum();
+    assert_eq!(sum, (0..32u64).map(|i| i * i).sum());
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ruost::test_utils::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::interrupts::pit;
+use ruost::time::{self, Instant};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    ruost::init();
+    test_main();
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+#[test_case]
+fn ticks_follow_tick_frequency() {
+    let before = time::ticks();
+    pit::sleep_ms(50);
+    let ticks = time::ticks() - before;
+    assert!((40..=60).contains(&ticks), "unexpected tick count {}", ticks);
+}
+
+#[test_case]
+fn tsc_is_calibrated() {
+    let hz = time::tsc_frequency().expect("TSC calibrated in time::init");
+    assert!(hz > 10_000_000); // anything able to run this is faster than 10 MHz
+}
+
+#[test_case]
+fn instant_is_monotonic() {
+    let mut last = Instant::now();
+    for _ in 0..1000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn elapsed_matches_busy_wait() {
+    let start = Instant::now();
+    pit::sleep_ms(20);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(18) && elapsed <= Duration::from_millis(30), "{:?}", elapsed);
+}
+
+#[test_case]
+fn uptime_agrees_with_ticks() {
+    let uptime = time::uptime().as_millis() as i64;
+    let ticks = time::ticks() as i64; // one tick per millisecond
+    assert!((uptime - ticks).abs() < 50 + 20, "uptime {} ms, {} ticks", uptime, ticks); // + calibration before ticks start
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let a = Instant::from_nanos(1_000);
+    let b = a + Duration::from_nanos(500);
+    assert_eq!(b.as_nanos(), 1_500);
+    assert_eq!(b - a, Duration::from_nanos(500));
+    assert_eq!(a - b, Duration::ZERO);
+    assert!(a.checked_add(Duration::MAX).is_none());
+}
@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::future::{pending, Future};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use ruost::task::{executor::{Executor, Spawner}, timer, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::time::Instant;
+use ruost::{allocator, memory, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    // executor only has timers to wait for most of the time, so this also covers its halt path
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests(executor.spawner())));
+    executor.run();
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("sleep_waits_for_duration", sleep_waits_for_duration()).await;
+    test("interval_ticks_periodically", interval_ticks_periodically()).await;
+    test("timeout_elapses", timeout_elapses()).await;
+    test("timeout_passes_output_through", timeout_passes_output_through()).await;
+    test("concurrent_sleeps_wake_in_order", concurrent_sleeps_wake_in_order()).await;
+    test("dropped_sleeps_leave_the_heap", dropped_sleeps_leave_the_heap()).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("timer::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+async fn sleep_waits_for_duration() {
+    let start = Instant::now();
+    timer::sleep(Duration::from_millis(30)).await;
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(30) && elapsed < Duration::from_millis(60), "{:?}", elapsed);
+}
+
+async fn interval_ticks_periodically() {
+    let start = Instant::now();
+    let mut interval = timer::interval(Duration::from_millis(10));
+    for _ in 0..5 {
+        interval.tick().await;
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_millis(80), "{:?}", elapsed);
+}
+
+async fn timeout_elapses() {
+    let result = timer::timeout(pending::<()>(), Duration::from_millis(10)).await;
+    assert_eq!(result, Err(timer::Elapsed));
+}
+
+async fn timeout_passes_output_through() {
+    let result = timer::timeout(async {
+        timer::sleep(Duration::from_millis(5)).await;
+        42
+    }, Duration::from_millis(100)).await;
+    assert_eq!(result, Ok(42));
+}
+
+async fn concurrent_sleeps_wake_in_order() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    let late = async {
+        timer::sleep(Duration::from_millis(20)).await;
+        assert_eq!(ORDER.fetch_add(1, Ordering::SeqCst), 1);
+    };
+    let early = async {
+        timer::sleep(Duration::from_millis(10)).await;
+        assert_eq!(ORDER.fetch_add(1, Ordering::SeqCst), 0);
+    };
+    futures_util::future::join(late, early).await;
+}
+
+async fn dropped_sleeps_leave_the_heap() {
+    let before = timer::pending_timers();
+    for _ in 0..1000 {
+        // finishes right away, the hour long sleep inside is dropped after being registered
+        let result = timer::timeout(timer::sleep(Duration::from_millis(1)), Duration::from_secs(3600)).await;
+        assert_eq!(result, Ok(()));
+    }
+    // cancelled entries are purged once they outnumber the live ones
+    assert!(timer::pending_timers() <= 2 * before + 2, "{} timers pending", timer::pending_timers());
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::future::{poll_fn, Future};
+use core::panic::PanicInfo;
+use core::task::Poll;
+use core::time::Duration;
+use ruost::task::{executor::{Executor, Spawner}, timer, yield_now, Priority, Task};
+use ruost::test_utils::{exit_qemu, QemuExitCode};
+use ruost::{allocator, memory, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    ruost::init();
+    memory::init(boot_info);
+    allocator::init();
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run_tests(executor.spawner())));
+    executor.run();
+
+    serial_print!("wake_queue::high_water_mark_is_reported...\t");
+    let normal = executor.queue_stats()[Priority::Normal as usize];
+    assert!(normal.high_water_mark >= 1000, "{:?}", normal); // all tasks of 'many_tasks' were ready at once
+    assert!(normal.high_water_mark <= normal.capacity);
+    serial_println!("[ok]");
+
+    exit_qemu(QemuExitCode::Success);
+    ruost::halt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    ruost::test_utils::test_panic_handler(info)
+}
+
+async fn run_tests(spawner: Spawner) {
+    test("repeated_wakeups_are_deduplicated", repeated_wakeups_are_deduplicated()).await;
+    test("many_tasks", many_tasks(&spawner)).await;
+    test("many_timers_expiring_together", many_timers_expiring_together(&spawner)).await;
+    spawner.shutdown();
+}
+
+async fn test(name: &str, test: impl Future<Output = ()>) {
+    serial_print!("wake_queue::{}...\t", name);
+    test.await;
+    serial_println!("[ok]");
+}
+
+async fn repeated_wakeups_are_deduplicated() {
+    // used to overflow the 100 entry queue
+    let mut polls = 0;
+    poll_fn(|context| {
+        polls += 1;
+        if polls == 3 {
+            return Poll::Ready(());
+        }
+        for _ in 0..1000 {
+            context.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }).await;
+    assert_eq!(polls, 3);
+}
+
+async fn many_tasks(spawner: &Spawner) {
+    let handles: Vec<_> = (0..2000u64)
+        .map(|i| spawner.spawn(Task::new(async move {
+            yield_now().await;
+            i
+        })))
+        .collect();
+    let mut sum = 0;
+    for handle in handles {
+        sum += handle.await.unwrap();
+    }
+    assert_eq!(sum, (0..2000).sum());
+}
+
+async fn many_timers_expiring_together(spawner: &Spawner) {
+    let deadline = ruost::time::Instant::now() + Duration::from_millis(10);
+    let handles: Vec<_> = (0..500)
+        .map(|_| spawner.spawn(Task::new(timer::sleep_until(deadline))))
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}